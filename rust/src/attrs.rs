@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use crate::ast::{TagAttr, TagAttrValue};
+
+// What a repeated attribute name means, e.g. "a" in {artigo a=3.2 !id="a" a=2}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    // Duplicates are errors
+    Reject,
+    // The last value is used (e.g. a=2)
+    #[default]
+    LastWins,
+    // The first value is used (e.g. a=3.2)
+    FirstWins,
+    // All values are kept (e.g. a=[3.2, 2])
+    Multi,
+}
+
+// A repeated attribute name. Indexes refer to the attributes in source order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateAttr {
+    name: String,
+    first: usize,
+    duplicate: usize,
+}
+
+impl DuplicateAttr {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn first(&self) -> usize {
+        self.first
+    }
+
+    pub fn duplicate(&self) -> usize {
+        self.duplicate
+    }
+}
+
+impl std::fmt::Display for DuplicateAttr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "attribute {:?} is repeated (attributes #{} and #{})",
+            self.name,
+            self.first + 1,
+            self.duplicate + 1
+        )
+    }
+}
+
+impl std::error::Error for DuplicateAttr {}
+
+// The attributes of a tag in source order. Duplicates are always kept so nothing is lost and
+// the policy only decides what lookups by name return.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AttrMap<'a> {
+    attrs: Vec<TagAttr<'a>>,
+    policy: DuplicatePolicy,
+}
+
+impl<'a> From<Vec<TagAttr<'a>>> for AttrMap<'a> {
+    fn from(attrs: Vec<TagAttr<'a>>) -> AttrMap<'a> {
+        AttrMap {
+            attrs: attrs,
+            policy: DuplicatePolicy::default(),
+        }
+    }
+}
+
+impl<'a> AttrMap<'a> {
+    pub fn policy(&self) -> DuplicatePolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: DuplicatePolicy) {
+        self.policy = policy;
+    }
+
+    pub fn with_policy(mut self, policy: DuplicatePolicy) -> AttrMap<'a> {
+        self.policy = policy;
+        self
+    }
+
+    pub fn into_owned(self) -> AttrMap<'static> {
+        AttrMap {
+            attrs: self.attrs.into_iter().map(TagAttr::into_owned).collect(),
+            policy: self.policy,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.attrs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attrs.is_empty()
+    }
+
+    // All attributes, including positional ones and duplicates, in source order
+    pub fn iter(&self) -> std::slice::Iter<'_, TagAttr<'a>> {
+        self.attrs.iter()
+    }
+
+    pub fn as_slice(&self) -> &[TagAttr<'a>] {
+        &self.attrs
+    }
+
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [TagAttr<'a>] {
+        &mut self.attrs
+    }
+
+    pub(crate) fn attrs_mut(&mut self) -> &mut Vec<TagAttr<'a>> {
+        &mut self.attrs
+    }
+
+    // Values without a name, in source order
+    pub fn positional(&self) -> impl Iterator<Item = &TagAttrValue<'a>> {
+        self.attrs
+            .iter()
+            .filter(|attr| attr.is_positional())
+            .filter_map(|attr| attr.value())
+    }
+
+    // Names are written as in the latest dialect, e.g. "id", "!id" or "tei:n" (see
+    // IdFullName::full_name)
+    fn named(&self, name: &str) -> Vec<(usize, &TagAttr<'a>)> {
+        self.attrs
+            .iter()
+            .enumerate()
+            .filter(|(_, attr)| match attr.name() {
+                Some(attr_name) => attr_name.full_name() == name,
+                None => false,
+            })
+            .collect()
+    }
+
+    // The attribute chosen by the policy. With DuplicatePolicy::Multi this is the first one.
+    pub fn get_attr(&self, name: &str) -> Result<Option<&TagAttr<'a>>, DuplicateAttr> {
+        let mut found = self.named(name).into_iter();
+        let first = match found.next() {
+            Some(first) => first,
+            None => return Ok(None),
+        };
+        let ans = match self.policy {
+            DuplicatePolicy::Reject => match found.next() {
+                Some(duplicate) => {
+                    return Err(DuplicateAttr {
+                        name: name.to_string(),
+                        first: first.0,
+                        duplicate: duplicate.0,
+                    })
+                }
+                None => first.1,
+            },
+            DuplicatePolicy::LastWins => found.last().unwrap_or(first).1,
+            DuplicatePolicy::FirstWins | DuplicatePolicy::Multi => first.1,
+        };
+        Ok(Some(ans))
+    }
+
+    // The value chosen by the policy. Bare names (e.g. "hidden") have no value.
+    pub fn get(&self, name: &str) -> Result<Option<&TagAttrValue<'a>>, DuplicateAttr> {
+        Ok(self.get_attr(name)?.and_then(|attr| attr.value()))
+    }
+
+    // Every value of the name with DuplicatePolicy::Multi, otherwise the one chosen by the policy
+    pub fn get_all(&self, name: &str) -> Result<Vec<&TagAttrValue<'a>>, DuplicateAttr> {
+        if self.policy == DuplicatePolicy::Multi {
+            return Ok(self
+                .named(name)
+                .into_iter()
+                .filter_map(|(_, attr)| attr.value())
+                .collect());
+        }
+        Ok(self.get(name)?.into_iter().collect())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        !self.named(name).is_empty()
+    }
+
+    // Every repetition of a name, regardless of the policy
+    pub fn duplicates(&self) -> Vec<DuplicateAttr> {
+        let mut ans = Vec::new();
+        // The first index of every name
+        let mut firsts: HashMap<String, usize> = HashMap::new();
+        for (pos, attr) in self.attrs.iter().enumerate() {
+            let name = match attr.name() {
+                Some(name) => name.full_name(),
+                None => continue,
+            };
+            match firsts.get(&name) {
+                Some(&first) => ans.push(DuplicateAttr {
+                    name: name,
+                    first: first,
+                    duplicate: pos,
+                }),
+                None => {
+                    firsts.insert(name, pos);
+                }
+            }
+        }
+        ans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::*;
+    use crate::attrs::*;
+
+    fn attrs(input: &str) -> AttrMap<'_> {
+        let (_, start) = curly_tag_start(input).unwrap();
+        start.args().clone()
+    }
+
+    #[test]
+    fn test_attr_map_policies() {
+        let map = attrs(r#"{artigo a=3 !id="a" hidden 7 a=2;"#);
+        assert_eq!(map.len(), 5);
+        assert_eq!(map.policy(), DuplicatePolicy::LastWins);
+        assert_eq!(
+            map.get("a"),
+            Ok(Some(&TagAttrValue::Integer("2".into(), 2)))
+        );
+        assert_eq!(
+            map.get("!id"),
+            Ok(Some(&TagAttrValue::String(
+                r#""a""#.into(),
+                "a".to_string()
+            )))
+        );
+        assert_eq!(map.get("id"), Ok(None));
+        assert_eq!(map.get("hidden"), Ok(None));
+        assert!(map.contains("hidden"));
+        assert_eq!(
+            map.positional().collect::<Vec<_>>(),
+            vec![&TagAttrValue::Integer("7".into(), 7)]
+        );
+
+        let map = map.with_policy(DuplicatePolicy::FirstWins);
+        assert_eq!(
+            map.get_all("a"),
+            Ok(vec![&TagAttrValue::Integer("3".into(), 3)])
+        );
+
+        let map = map.with_policy(DuplicatePolicy::Multi);
+        assert_eq!(
+            map.get("a"),
+            Ok(Some(&TagAttrValue::Integer("3".into(), 3)))
+        );
+        assert_eq!(
+            map.get_all("a"),
+            Ok(vec![
+                &TagAttrValue::Integer("3".into(), 3),
+                &TagAttrValue::Integer("2".into(), 2)
+            ])
+        );
+
+        let dup = DuplicateAttr {
+            name: "a".to_string(),
+            first: 0,
+            duplicate: 4,
+        };
+        let map = map.with_policy(DuplicatePolicy::Reject);
+        assert_eq!(map.get("a"), Err(dup.clone()));
+        assert_eq!(map.get_all("a"), Err(dup.clone()));
+        assert_eq!(map.get("hidden"), Ok(None));
+        assert_eq!(map.duplicates(), vec![dup.clone()]);
+        assert_eq!(
+            dup.to_string(),
+            r#"attribute "a" is repeated (attributes #1 and #5)"#
+        );
+    }
+
+    #[test]
+    fn test_attr_map_duplicates() {
+        assert_eq!(attrs("{a x=1 y=2 1 1;").duplicates(), vec![]);
+        assert_eq!(
+            attrs("{a x=1 tei:x=2 x x=3;").duplicates(),
+            vec![
+                DuplicateAttr {
+                    name: "x".to_string(),
+                    first: 0,
+                    duplicate: 2,
+                },
+                DuplicateAttr {
+                    name: "x".to_string(),
+                    first: 0,
+                    duplicate: 3,
+                }
+            ]
+        );
+
+        // Many attributes are checked in one pass
+        let names: String = (0..20_000).map(|pos| format!(" a{}=1", pos)).collect();
+        let src = format!("{{a{} a0=2;", names);
+        let dups = attrs(&src).duplicates();
+        assert_eq!(dups.len(), 1);
+        assert_eq!((dups[0].first(), dups[0].duplicate()), (0, 20_000));
+    }
+}
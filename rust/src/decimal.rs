@@ -0,0 +1,259 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+use crate::number::{parse_number, NumberError};
+
+// An exact decimal number: digits × 10^-scale. E.g. "1.10" is stored as ("110", 2) and "1.1" as
+// ("11", 1), so both keep their precision while still comparing as equal numbers.
+#[derive(Debug, Clone)]
+pub struct Decimal {
+    negative: bool,
+    // ASCII digits without leading zeros ("0" for zero)
+    digits: String,
+    scale: i64,
+}
+
+impl Decimal {
+    pub fn new(negative: bool, digits: &str, scale: i64) -> Result<Decimal, NumberError> {
+        if digits.is_empty() {
+            return Err(NumberError::NoDigits);
+        }
+        if let Some(ch) = digits.chars().find(|ch| !ch.is_ascii_digit()) {
+            return Err(NumberError::InvalidDigit(ch));
+        }
+        let digits = digits.trim_start_matches('0');
+        let digits = if digits.is_empty() { "0" } else { digits };
+        Ok(Decimal {
+            negative,
+            digits: digits.to_string(),
+            scale,
+        })
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative && !self.is_zero()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits == "0"
+    }
+
+    pub fn digits(&self) -> &str {
+        &self.digits
+    }
+
+    // Number of digits after the decimal point (negative for things like "1e3")
+    pub fn scale(&self) -> i64 {
+        self.scale
+    }
+
+    // Digits and scale without trailing zeros, e.g. ("11", 1) for both "1.10" and "1.1"
+    fn normalized(&self) -> (&str, i64) {
+        if self.is_zero() {
+            return ("0", 0);
+        }
+        let digits = self.digits.trim_end_matches('0');
+        let n_zeros = (self.digits.len() - digits.len()) as i64;
+        (digits, self.scale.saturating_sub(n_zeros))
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.normalized().1 <= 0
+    }
+
+    pub fn to_i64(&self) -> Result<i64, NumberError> {
+        let (digits, scale) = self.normalized();
+        if scale > 0 {
+            return Err(NumberError::NotAnInteger);
+        }
+        let mut magnitude: u64 = 0;
+        let n_zeros = usize::try_from(-scale).map_err(|_| NumberError::Overflow)?;
+        for ch in digits.chars().chain(std::iter::repeat_n('0', n_zeros)) {
+            magnitude = magnitude
+                .checked_mul(10)
+                .and_then(|val| val.checked_add(ch as u64 - '0' as u64))
+                .ok_or(NumberError::Overflow)?;
+        }
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+                .ok_or(NumberError::Overflow)
+        } else {
+            i64::try_from(magnitude).map_err(|_| NumberError::Overflow)
+        }
+    }
+
+    // The nearest f64, which may lose precision
+    pub fn to_f64(&self) -> Result<f64, NumberError> {
+        let sign = if self.negative { "-" } else { "" };
+        let ans: f64 = format!("{}{}e{}", sign, self.digits, -(self.scale as i128))
+            .parse()
+            .map_err(|_| NumberError::Overflow)?;
+        if ans.is_infinite() {
+            return Err(NumberError::Overflow);
+        }
+        Ok(ans)
+    }
+
+    // Position of the most significant digit relative to the decimal point
+    fn magnitude_order(&self) -> i128 {
+        let (digits, scale) = self.normalized();
+        digits.len() as i128 - scale as i128
+    }
+
+    fn cmp_magnitude(&self, other: &Decimal) -> Ordering {
+        match (self.is_zero(), other.is_zero()) {
+            (true, true) => return Ordering::Equal,
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            (false, false) => {}
+        }
+        // Without trailing zeros, comparing the digits as strings compares the values
+        self.magnitude_order()
+            .cmp(&other.magnitude_order())
+            .then_with(|| self.normalized().0.cmp(other.normalized().0))
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(val: i64) -> Decimal {
+        Decimal {
+            negative: val < 0,
+            digits: val.unsigned_abs().to_string(),
+            scale: 0,
+        }
+    }
+}
+
+impl std::str::FromStr for Decimal {
+    type Err = NumberError;
+
+    fn from_str(input: &str) -> Result<Decimal, NumberError> {
+        parse_number(input)?.to_decimal()
+    }
+}
+
+// Display writes tiny numbers like "1e-99999999999" with an exponent instead of this many zeros
+const MAX_LEADING_ZEROS: i64 = 32;
+
+// Writes the plain decimal form keeping the scale, e.g. "1.10", "-0.05" or "1e3"
+impl std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_negative() {
+            write!(f, "-")?;
+        }
+        if self.scale < 0 || self.scale - self.digits.len() as i64 > MAX_LEADING_ZEROS {
+            return write!(f, "{}e{}", self.digits, -(self.scale as i128));
+        }
+        if self.scale == 0 {
+            return write!(f, "{}", self.digits);
+        }
+        let scale = self.scale as usize;
+        let padded = format!("{:0>width$}", self.digits, width = scale + 1);
+        let (int_part, frac_part) = padded.split_at(padded.len() - scale);
+        write!(f, "{}.{}", int_part, frac_part)
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        match (self.is_negative(), other.is_negative()) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => self.cmp_magnitude(other),
+            (true, true) => other.cmp_magnitude(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decimal::*;
+
+    fn dec(input: &str) -> Decimal {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    fn test_decimal_parse() {
+        let val = dec("1.10");
+        assert_eq!(val.digits(), "110");
+        assert_eq!(val.scale(), 2);
+        let val = dec("-007,050");
+        assert_eq!(val.digits(), "7050");
+        assert_eq!(val.scale(), 3);
+        assert!(val.is_negative());
+        let val = dec("1.5e3");
+        assert_eq!(val.digits(), "15");
+        assert_eq!(val.scale(), -2);
+        assert_eq!("".parse::<Decimal>(), Err(NumberError::NoDigits));
+        assert_eq!("1e".parse::<Decimal>(), Err(NumberError::MissingExponent));
+        assert_eq!(
+            Decimal::new(false, "1a", 0),
+            Err(NumberError::InvalidDigit('a'))
+        );
+    }
+
+    #[test]
+    fn test_decimal_cmp() {
+        assert_eq!(dec("1.10"), dec("1.1"));
+        assert_eq!(dec("1e3"), dec("1000"));
+        assert_eq!(dec("-0.0"), dec("0"));
+        assert!(dec("1.09") < dec("1.1"));
+        assert!(dec("-2") < dec("-1.5"));
+        assert!(dec("-0.1") < dec("0"));
+        assert!(dec("0.001") < dec("0.01"));
+        assert!(dec("99.9") < dec("100"));
+        assert!(dec("12345678901234567890.5") > dec("12345678901234567890.25"));
+    }
+
+    #[test]
+    fn test_decimal_to_string() {
+        assert_eq!(dec("1.10").to_string(), "1.10");
+        assert_eq!(dec("-.05").to_string(), "-0.05");
+        assert_eq!(dec("+3,14").to_string(), "3.14");
+        assert_eq!(dec("1_000").to_string(), "1000");
+        assert_eq!(dec("2e3").to_string(), "2e3");
+        assert_eq!(dec("-0.0").to_string(), "0.0");
+        assert_eq!(
+            dec("12345678901234567890.123456789").to_string(),
+            "12345678901234567890.123456789"
+        );
+        assert_eq!(dec("1e-5").to_string(), "0.00001");
+        assert_eq!(dec("1e-33").to_string(), format!("0.{}1", "0".repeat(32)));
+        assert_eq!(dec("-25e-35").to_string(), "-25e-35");
+        // Without writing the zeros
+        assert_eq!(dec("1e-99999999999").to_string(), "1e-99999999999");
+        assert_eq!(
+            dec(&dec("1.5e-99999999999").to_string()),
+            dec("1.5e-99999999999")
+        );
+    }
+
+    #[test]
+    fn test_decimal_to_i64_and_f64() {
+        assert_eq!(dec("42.000").to_i64(), Ok(42));
+        assert_eq!(dec("-1.5e1").to_i64(), Ok(-15));
+        assert_eq!(dec("-9223372036854775808").to_i64(), Ok(i64::MIN));
+        assert_eq!(dec("1.5").to_i64(), Err(NumberError::NotAnInteger));
+        assert_eq!(dec("1e19").to_i64(), Err(NumberError::Overflow));
+        assert_eq!(dec("1.25").to_f64(), Ok(1.25));
+        assert_eq!(dec("-125e-2").to_f64(), Ok(-1.25));
+        assert_eq!(dec("1e400").to_f64(), Err(NumberError::Overflow));
+        assert_eq!(Decimal::from(-12).to_string(), "-12");
+        assert_eq!(Decimal::from(i64::MIN).to_i64(), Ok(i64::MIN));
+    }
+}
@@ -0,0 +1,699 @@
+// Turns nom errors into ParseErrors. The nom errors only say where some parser gave up, so the
+// input is examined again from there to find out what is actually wrong with it.
+
+use nom::error::{Error as NomError, ErrorKind};
+use nom::Offset;
+
+use crate::ast::{
+    comment_syntax, current_dialect, idfullname, max_depth, nodes, parse_iri_ref,
+    parse_special_char, tag_args, tag_args_dict_key, tag_args_item, tag_args_trivia,
+    tag_args_value, view_prefix, xid_name, IdFullName, Node, TOO_DEEP,
+};
+use crate::dialect::Dialect;
+use crate::number::{number_lexeme_len, NumberError, NumberLiteral};
+use crate::pos::{Position, Span};
+use crate::prelude::{ParseError, ParseErrorKind};
+
+// The span of at[..len], where at is a slice of src
+fn span_at(src: &str, at: &str, len: usize) -> Span {
+    let start = Position::new().after(&src[..src.offset(at)]);
+    Span::new_from_to(start, start.after(&at[..len]))
+}
+
+fn error_at(src: &str, at: &str, len: usize, kind: ParseErrorKind) -> ParseError {
+    ParseError::new(kind, span_at(src, at, len))
+}
+
+fn unexpected(src: &str, at: &str, expected: &[&str]) -> ParseError {
+    match at.chars().next() {
+        Some(ch) => error_at(src, at, ch.len_utf8(), ParseErrorKind::UnexpectedChar(ch)),
+        None => error_at(src, at, 0, ParseErrorKind::UnexpectedEnd),
+    }
+    .expecting(expected)
+}
+
+fn bad_name(src: &str, at: &str) -> ParseError {
+    match at.chars().next() {
+        Some(ch) => {
+            error_at(src, at, ch.len_utf8(), ParseErrorKind::InvalidName).expecting(&["name"])
+        }
+        None => unexpected(src, at, &["name"]),
+    }
+}
+
+// E.g. "\q" or "\u{D800}"
+fn bad_escape(src: &str, at: &str) -> ParseError {
+    let other_dialect = match current_dialect() {
+        Dialect::V1 => Dialect::V2,
+        Dialect::V2 => Dialect::V1,
+    };
+    if let Some((_, len)) = other_dialect.decode_unicode_escape(at) {
+        let kind = ParseErrorKind::OnlyInDialect(at[..len].to_string(), other_dialect);
+        return error_at(src, at, len, kind);
+    }
+    let len = match at[1..].chars().next() {
+        Some('u') if at[2..].starts_with('{') => at.find('}').map_or(3, |pos| pos + 1),
+        Some(ch) => 1 + ch.len_utf8(),
+        None => 1,
+    };
+    error_at(
+        src,
+        at,
+        len,
+        ParseErrorKind::BadEscape(at[..len].to_string()),
+    )
+}
+
+// The length of the malformed number at the start of the input and what is wrong with it
+fn number_error(at: &str) -> Option<(usize, NumberError)> {
+    // Lists and dictionaries don't have decimal commas, so both ways are tried
+    for decimal_comma in [true, false] {
+        let len = number_lexeme_len(at, decimal_comma);
+        if len == 0 {
+            return None;
+        }
+        let checked =
+            NumberLiteral::parse(&at[..len]).and_then(|literal| match literal.is_integer() {
+                true => literal.to_i64().map(|_| ()),
+                false => literal.to_decimal().map(|_| ()),
+            });
+        if let Err(err) = checked {
+            return Some((len, err));
+        }
+    }
+    None
+}
+
+// E.g. "{- abc" without the "-}"
+fn unterminated_comment(src: &str, at: &str) -> Option<ParseError> {
+    let (open, close) = comment_syntax(at)?.delimiters();
+    Some(error_at(src, at, open.len(), ParseErrorKind::UnterminatedComment).expecting(&[close]))
+}
+
+fn skip_whitespace(input: &str) -> &str {
+    input.trim_start_matches([' ', '\t', '\r', '\n'])
+}
+
+// Skips what separates items in lists and dictionaries, e.g. " , "
+fn skip_separator(input: &str) -> &str {
+    let input = skip_whitespace(input);
+    skip_whitespace(input.strip_prefix(',').unwrap_or(input))
+}
+
+pub(crate) fn diagnose<'a>(src: &'a str, err: nom::Err<NomError<&'a str>>) -> ParseError {
+    match err {
+        nom::Err::Failure(err) => diagnose_failure(src, err),
+        // The whole input is parsed as nodes, so errors are where the last node ended
+        nom::Err::Error(err) => diagnose_node(src, err.input),
+        nom::Err::Incomplete(_) => unexpected(src, &src[src.len()..], &[]),
+    }
+}
+
+// Failures point exactly at the problem
+pub(crate) fn diagnose_failure<'a>(src: &'a str, err: NomError<&'a str>) -> ParseError {
+    let at = err.input;
+    match err.code {
+        ErrorKind::Char if at.starts_with('\\') => bad_escape(src, at),
+        ErrorKind::Digit | ErrorKind::TooLarge => match number_error(at) {
+            Some((len, err)) => error_at(src, at, len, ParseErrorKind::InvalidNumber(err)),
+            None => unexpected(src, at, &[]),
+        },
+        ErrorKind::Verify if at.starts_with('<') => diagnose_url(src, at),
+        TOO_DEEP => error_at(src, at, 1, ParseErrorKind::TooDeeplyNested(max_depth())),
+        _ => unexpected(src, at, &[]),
+    }
+}
+
+// Finds out why no node could be parsed at the start of the input
+pub(crate) fn diagnose_node<'a>(src: &'a str, at: &'a str) -> ParseError {
+    let mut chars = at.chars();
+    let (first, second) = (chars.next(), chars.next());
+    // A lone delimiter (e.g. "a < b") was probably meant to be text
+    if let Some(delimiter @ ('{' | '<' | '|')) = first {
+        if second.is_none_or(char::is_whitespace) {
+            let escaped = format!("\\{}", delimiter);
+            return unexpected(src, at, &[&escaped]);
+        }
+    }
+    if let Some(err) = unterminated_comment(src, at) {
+        return err;
+    }
+    match first {
+        None => unexpected(src, at, &[]),
+        Some('{') => diagnose_curly_tag(src, at),
+        Some('<') => diagnose_pointy_tag_start(src, at),
+        Some('|') => diagnose_pointy_tag_end(src, at),
+        Some('}') => error_at(src, at, 1, ParseErrorKind::UnmatchedCurlyClose),
+        Some('\\') => bad_escape(src, at),
+        Some(fence @ ('`' | '$')) => {
+            let fence_len = at.len() - at.trim_start_matches(fence).len();
+            let kind = match fence {
+                '`' => ParseErrorKind::UnterminatedCodeBlock,
+                _ => ParseErrorKind::UnterminatedTexCode,
+            };
+            error_at(src, at, fence_len, kind).expecting(&[&at[..fence_len]])
+        }
+        Some(_) => unexpected(src, at, &[]),
+    }
+}
+
+// E.g. "(t)" in "<(t)line|". Returns what comes after it.
+fn diagnose_view<'a>(src: &'a str, at: &'a str) -> Result<&'a str, ParseError> {
+    let inner = &at[1..];
+    let rest = match xid_name(inner) {
+        Ok((rest, _)) => rest,
+        Err(_) => return Err(bad_name(src, inner)),
+    };
+    match rest.strip_prefix(')') {
+        Some(rest) => Ok(rest),
+        None => Err(unexpected(src, rest, &[")"])),
+    }
+}
+
+// Checks "{name attrs", "<(view)name attrs" or "<view/name attrs" and returns what comes after the
+// name and after the attributes
+fn diagnose_tag_head<'a>(src: &'a str, at: &'a str) -> Result<(&'a str, &'a str), ParseError> {
+    let mut rest = &at[1..];
+    if at.starts_with('<') {
+        rest = match view_prefix(rest) {
+            Ok((after_view, _)) => after_view,
+            Err(_) if rest.starts_with('(') => diagnose_view(src, rest)?,
+            Err(_) => rest,
+        };
+    } else if current_dialect().has_curly_views() && rest.starts_with('(') {
+        rest = diagnose_view(src, rest)?;
+    }
+    let after_name = match idfullname(rest) {
+        Ok((after_name, _)) => after_name,
+        Err(_) => return Err(bad_name(src, rest)),
+    };
+    let rest = match tag_args(after_name) {
+        Ok((rest, _)) => rest,
+        Err(nom::Err::Failure(err)) => return Err(diagnose_failure(src, err)),
+        Err(_) => after_name,
+    };
+    let rest = tag_args_trivia(rest).map_or(rest, |(rest, _)| rest);
+    Ok((after_name, rest))
+}
+
+fn diagnose_curly_tag<'a>(src: &'a str, at: &'a str) -> ParseError {
+    let (after_name, rest) = match diagnose_tag_head(src, at) {
+        Ok(ans) => ans,
+        Err(err) => return err,
+    };
+    let head_len = at.len() - after_name.len();
+    let content = match rest.strip_prefix(';') {
+        Some(content) => content,
+        None if rest.is_empty() => {
+            return error_at(src, at, head_len, ParseErrorKind::UnterminatedCurlyTag)
+                .expecting(&[";", "}"])
+        }
+        None => return diagnose_attr(src, rest, &[";", "}"]),
+    };
+    match nodes(content) {
+        Ok(("", _)) => {
+            error_at(src, at, head_len, ParseErrorKind::UnterminatedCurlyTag).expecting(&["}"])
+        }
+        Ok((rest, _)) => diagnose_node(src, rest),
+        Err(nom::Err::Failure(err)) => diagnose_failure(src, err),
+        Err(_) => diagnose_node(src, content),
+    }
+}
+
+fn diagnose_pointy_tag_start<'a>(src: &'a str, at: &'a str) -> ParseError {
+    let (after_name, rest) = match diagnose_tag_head(src, at) {
+        Ok(ans) => ans,
+        Err(err) => return err,
+    };
+    if rest.is_empty() {
+        let head_len = at.len() - after_name.len();
+        return error_at(src, at, head_len, ParseErrorKind::UnterminatedPointyTag)
+            .expecting(&["|"]);
+    }
+    diagnose_attr(src, rest, &["|"])
+}
+
+fn diagnose_pointy_tag_end<'a>(src: &'a str, at: &'a str) -> ParseError {
+    let mut rest = &at[1..];
+    if let Some(view) = rest.strip_prefix('/') {
+        // E.g. "|/t>", which has no element name
+        rest = match xid_name(view) {
+            Ok((after_view, _)) => after_view,
+            Err(_) => return bad_name(src, view),
+        };
+    } else {
+        rest = match view_prefix(rest) {
+            Ok((after_view, _)) => after_view,
+            Err(_) if rest.starts_with('(') => match diagnose_view(src, rest) {
+                Ok(rest) => rest,
+                Err(err) => return err,
+            },
+            Err(_) => rest,
+        };
+        if let Ok((after_name, _)) = idfullname(rest) {
+            rest = after_name;
+        }
+    }
+    match rest {
+        "" => error_at(src, at, at.len(), ParseErrorKind::UnterminatedPointyTag).expecting(&[">"]),
+        _ => unexpected(src, rest, &[">"]),
+    }
+}
+
+// What comes after the attributes of a tag is neither another attribute nor the end of the tag
+fn diagnose_attr<'a>(src: &'a str, at: &'a str, closers: &[&str]) -> ParseError {
+    if let Some(err) = unterminated_comment(src, at) {
+        return err;
+    }
+    let after_trivia = src[..src.offset(at)]
+        .chars()
+        .next_back()
+        .is_some_and(|ch| ch.is_whitespace() || ch == '}');
+    if !after_trivia {
+        // E.g. "{a x=}" is read as the bare name "x" followed by "=}". However, in "{x=1}" the
+        // problem is the "=" as the tag itself can't have a value.
+        if let Some(value) = at.strip_prefix('=') {
+            if tag_args_value(value).is_err() {
+                return diagnose_value(src, value, &["value"]);
+            }
+        }
+        let mut expected = closers.to_vec();
+        expected.push("whitespace");
+        return unexpected(src, at, &expected);
+    }
+    if let Ok((after_name, _)) = idfullname(at) {
+        if let Some(value) = after_name.strip_prefix('=') {
+            return diagnose_value(src, value, &["value"]);
+        }
+    }
+    let mut expected = closers.to_vec();
+    expected.push("attribute");
+    diagnose_value(src, at, &expected)
+}
+
+// Finds out why no value could be parsed at the start of the input
+fn diagnose_value<'a>(src: &'a str, at: &'a str, expected: &[&str]) -> ParseError {
+    match at.chars().next() {
+        Some('"') => diagnose_string(src, at),
+        Some('[') => diagnose_list(src, at),
+        Some('{') => diagnose_dict(src, at),
+        Some('<') if at.starts_with("<>") => diagnose_nodes_value(src, at),
+        Some('<') => diagnose_url(src, at),
+        _ => match number_error(at) {
+            Some((len, err)) => error_at(src, at, len, ParseErrorKind::InvalidNumber(err)),
+            None => unexpected(src, at, expected),
+        },
+    }
+}
+
+fn diagnose_string<'a>(src: &'a str, at: &'a str) -> ParseError {
+    let mut rest = &at[1..];
+    loop {
+        match rest.chars().next() {
+            None => {
+                return error_at(src, at, 1, ParseErrorKind::UnterminatedString).expecting(&["\""])
+            }
+            Some('\\') => match parse_special_char(false, rest) {
+                Ok((new_rest, _)) => rest = new_rest,
+                Err(_) => return bad_escape(src, rest),
+            },
+            // The string itself is fine
+            Some('"') => return unexpected(src, &rest[1..], &[]),
+            Some(ch) => rest = &rest[ch.len_utf8()..],
+        }
+    }
+}
+
+fn diagnose_url<'a>(src: &'a str, at: &'a str) -> ParseError {
+    let inner = &at[1..];
+    let iri_len = inner
+        .find(|ch: char| ch == '<' || ch == '>' || ch.is_whitespace())
+        .unwrap_or(inner.len());
+    let rest = &inner[iri_len..];
+    if !rest.starts_with('>') {
+        return unexpected(src, rest, &[">"]);
+    }
+    let iri = &inner[..iri_len];
+    match parse_iri_ref(iri) {
+        Ok(_) => unexpected(src, &rest[1..], &[]),
+        Err(_) => error_at(
+            src,
+            at,
+            iri_len + "<>".len(),
+            ParseErrorKind::InvalidUrl(iri.to_string()),
+        ),
+    }
+}
+
+fn diagnose_list<'a>(src: &'a str, at: &'a str) -> ParseError {
+    let mut rest = skip_whitespace(&at[1..]);
+    loop {
+        if let Some(after) = rest.strip_prefix(']') {
+            return unexpected(src, after, &[]);
+        }
+        rest = match tag_args_item(rest) {
+            Ok((after, _)) => skip_separator(after),
+            Err(nom::Err::Failure(err)) => return diagnose_failure(src, err),
+            Err(_) => return diagnose_value(src, rest, &["]", "value"]),
+        };
+    }
+}
+
+fn diagnose_dict<'a>(src: &'a str, at: &'a str) -> ParseError {
+    let mut rest = skip_whitespace(&at[1..]);
+    loop {
+        if let Some(after) = rest.strip_prefix('}') {
+            return unexpected(src, after, &[]);
+        }
+        let after_key = match tag_args_dict_key(rest) {
+            Ok((after_key, _)) => skip_whitespace(after_key),
+            Err(_) if rest.starts_with('"') => return diagnose_string(src, rest),
+            Err(_) => return unexpected(src, rest, &["}", "key"]),
+        };
+        let value = match after_key.strip_prefix(':') {
+            Some(value) => skip_whitespace(value),
+            None => return unexpected(src, after_key, &[":"]),
+        };
+        rest = match tag_args_item(value) {
+            Ok((after, _)) => skip_separator(after),
+            Err(nom::Err::Failure(err)) => return diagnose_failure(src, err),
+            Err(_) => return diagnose_value(src, value, &["value"]),
+        };
+    }
+}
+
+// E.g. "<>some {b; text}</>"
+fn diagnose_nodes_value<'a>(src: &'a str, at: &'a str) -> ParseError {
+    match nodes(&at[2..]) {
+        Ok((rest, _)) if rest.starts_with("</>") => unexpected(src, &rest[3..], &[]),
+        Ok(("", _)) => unexpected(src, &at[at.len()..], &["</>"]),
+        Ok((rest, _)) => diagnose_node(src, rest),
+        Err(nom::Err::Failure(err)) => diagnose_failure(src, err),
+        Err(_) => diagnose_node(src, &at[2..]),
+    }
+}
+
+// A pointy tag that is still open
+struct OpenElement<'a> {
+    view: &'a str,
+    element: IdFullName<'a>,
+    span: Span,
+}
+
+// "|(g)sentence>" may close "<(g)tei:sentence|" as well as "<(g)sentence|"
+pub(crate) fn closes(end: &IdFullName, start: &IdFullName) -> bool {
+    end.localname() == start.localname()
+        && (end.namespace().is_empty() || end.namespace() == start.namespace())
+}
+
+fn check_view_nodes<'a>(
+    nodes: &'a [Node<'a>],
+    open: &mut Vec<OpenElement<'a>>,
+    errors: &mut Vec<ParseError>,
+) {
+    for node in nodes {
+        match node {
+            Node::CurlyTag(tag) => check_view_nodes(tag.content(), open, errors),
+            Node::PointyTagStart(tag) => open.push(OpenElement {
+                view: tag.view(),
+                element: tag.element().clone(),
+                span: tag.span(),
+            }),
+            Node::PointyTagEnd(tag) => {
+                let view = tag.view();
+                let innermost = open.iter().rposition(|item| item.view == view);
+                let closed = match tag.element() {
+                    Some(element) => open
+                        .iter()
+                        .rposition(|item| item.view == view && closes(element, &item.element)),
+                    None => innermost,
+                };
+                let element = tag.element().map(|element| element.encode_cptml());
+                let element = element.unwrap_or_default();
+                match (closed, innermost) {
+                    (Some(closed), Some(innermost)) if closed != innermost => {
+                        errors.push(ParseError::new(
+                            ParseErrorKind::MismatchedViewClose {
+                                view: view.to_string(),
+                                element: element,
+                                open: open[innermost].element.encode_cptml(),
+                            },
+                            tag.span(),
+                        ));
+                    }
+                    (None, _) => {
+                        errors.push(ParseError::new(
+                            ParseErrorKind::UnknownViewClose {
+                                view: view.to_string(),
+                                element: element,
+                            },
+                            tag.span(),
+                        ));
+                    }
+                    _ => {}
+                }
+                // The elements of the view that were opened inside the closed one are closed
+                // with it, so that they are not reported again as never closed
+                if let Some(closed) = closed {
+                    let mut pos = 0;
+                    open.retain(|item| {
+                        pos += 1;
+                        pos <= closed || item.view != view
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Pointy tags of the same view must be properly nested, although tags of different views may
+// overlap each other and curly tags. Returns every problem in document order.
+pub(crate) fn view_errors<'a>(nodes: &'a [Node<'a>]) -> Vec<ParseError> {
+    let mut open = Vec::new();
+    let mut errors = Vec::new();
+    check_view_nodes(nodes, &mut open, &mut errors);
+    for item in open {
+        let view = match item.view {
+            "" => String::new(),
+            view => format!("({})", view),
+        };
+        let end = format!("|{}{}>", view, item.element.encode_cptml());
+        errors.push(
+            ParseError::new(
+                ParseErrorKind::UnclosedViewElement {
+                    view: item.view.to_string(),
+                    element: item.element.encode_cptml(),
+                },
+                item.span,
+            )
+            .expecting(&[&end]),
+        );
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::parse_document;
+    use crate::dialect::Dialect;
+    use crate::number::NumberError;
+    use crate::pos::Span;
+    use crate::prelude::{CptmlError, ParseError, ParseErrorKind};
+
+    fn err(src: &str) -> ParseError {
+        match parse_document(src) {
+            Err(CptmlError::Parse(err)) => err,
+            ans => panic!("{:?} gave {:?}", src, ans),
+        }
+    }
+
+    // A span within the first line of ASCII text
+    fn sp(start: usize, end: usize) -> Span {
+        Span::new2(start, 1, start, end, 1, end)
+    }
+
+    fn check(src: &str, kind: ParseErrorKind, span: Span, expected: &[&str]) {
+        let expected: Vec<String> = expected.iter().map(|item| item.to_string()).collect();
+        let err = err(src);
+        assert_eq!(
+            (err.kind(), err.span(), err.expected()),
+            (&kind, span, &expected[..]),
+            "{:?}",
+            src
+        );
+    }
+
+    #[test]
+    fn test_diagnose_syntax() {
+        use ParseErrorKind::*;
+        check("{- abc", UnterminatedComment, sp(0, 2), &["-}"]);
+        check("{p; {- x}", UnterminatedComment, sp(4, 6), &["-}"]);
+        check("{p x {% y}", UnterminatedComment, sp(5, 7), &["%}"]);
+        // Version 1 documents have other comments and curly tags with views
+        let v1_span = |start, end| Span::new2(19 + start, 2, start, 19 + end, 2, end);
+        let v1 = |src: &str| format!("{{.cptml version=1}}\n{}", src);
+        check(
+            &v1("{p; a /* b}"),
+            UnterminatedComment,
+            v1_span(6, 8),
+            &["*/"],
+        );
+        check(&v1("{(1)p}"), InvalidName, v1_span(2, 3), &["name"]);
+        check("{p; hi", UnterminatedCurlyTag, sp(0, 2), &["}"]);
+        check("{p x=1", UnterminatedCurlyTag, sp(0, 2), &[";", "}"]);
+        check("<(t)line x=1", UnterminatedPointyTag, sp(0, 8), &["|"]);
+        check("|(t)line", UnterminatedPointyTag, sp(0, 8), &[">"]);
+        check("<t/line x=1", UnterminatedPointyTag, sp(0, 7), &["|"]);
+        check("|t/line", UnterminatedPointyTag, sp(0, 7), &[">"]);
+        check("|/t", UnterminatedPointyTag, sp(0, 3), &[">"]);
+        check("|/1>", InvalidName, sp(2, 3), &["name"]);
+        check("``code`", UnterminatedCodeBlock, sp(0, 2), &["``"]);
+        check("$$x^2$", UnterminatedTexCode, sp(0, 2), &["$$"]);
+        check("{p x=[1, \"ab]}", UnterminatedString, sp(9, 10), &["\""]);
+        check("a \\q b", BadEscape("\\q".to_string()), sp(2, 4), &[]);
+        check(
+            "{p x=\"a\\qb\"}",
+            BadEscape("\\q".to_string()),
+            sp(7, 9),
+            &[],
+        );
+        check(
+            "{p x=\"\\u{D800}\"}",
+            BadEscape("\\u{D800}".to_string()),
+            sp(6, 14),
+            &[],
+        );
+        check(
+            "a \\uE9; b",
+            OnlyInDialect("\\uE9;".to_string(), Dialect::V1),
+            sp(2, 7),
+            &[],
+        );
+        check(
+            "{p x=\"\\u1F600;\"}",
+            OnlyInDialect("\\u1F600;".to_string(), Dialect::V1),
+            sp(6, 14),
+            &[],
+        );
+        assert_eq!(
+            err("\\uE9;").to_string(),
+            r#"1:1: "\\uE9;" is only valid in CPTML version 1"#
+        );
+        check(
+            "{p x=1_}",
+            InvalidNumber(NumberError::MisplacedUnderscore),
+            sp(5, 7),
+            &[],
+        );
+        check(
+            "{p x=[99999999999999999999]}",
+            InvalidNumber(NumberError::Overflow),
+            sp(6, 26),
+            &[],
+        );
+        check(
+            "{p x=<http://[::1>}",
+            InvalidUrl("http://[::1".to_string()),
+            sp(5, 18),
+            &[],
+        );
+        check("{1b}", InvalidName, sp(1, 2), &["name"]);
+        check("<(1)line|", InvalidName, sp(2, 3), &["name"]);
+        check("<(t line|", UnexpectedChar(' '), sp(3, 4), &[")"]);
+        check("}", UnmatchedCurlyClose, sp(0, 1), &[]);
+        check("{p; hi}}", UnmatchedCurlyClose, sp(7, 8), &[]);
+        check("a < b", UnexpectedChar('<'), sp(2, 3), &["\\<"]);
+        check("{p x=}", UnexpectedChar('}'), sp(5, 6), &["value"]);
+        check(
+            "{p truex}",
+            UnexpectedChar('x'),
+            sp(7, 8),
+            &[";", "}", "whitespace"],
+        );
+        check(
+            "{p x=[1, @]}",
+            UnexpectedChar('@'),
+            sp(9, 10),
+            &["]", "value"],
+        );
+        check("{p x={a 1}}", UnexpectedChar('1'), sp(8, 9), &[":"]);
+        check("{p x=<>{b}", UnexpectedEnd, sp(10, 10), &["</>"]);
+    }
+
+    #[test]
+    fn test_diagnose_views() {
+        use ParseErrorKind::*;
+        let view_error = |view: &str, element: &str| UnknownViewClose {
+            view: view.to_string(),
+            element: element.to_string(),
+        };
+        check("|(t)line>", view_error("t", "line"), sp(0, 9), &[]);
+        check(
+            "<(g)line||(g)line>|(t)line>",
+            view_error("t", "line"),
+            sp(18, 27),
+            &[],
+        );
+        check("x|>", view_error("", ""), sp(1, 3), &[]);
+        check(
+            "<(t)a|<(t)b||(t)a>",
+            MismatchedViewClose {
+                view: "t".to_string(),
+                element: "a".to_string(),
+                open: "b".to_string(),
+            },
+            sp(12, 18),
+            &[],
+        );
+        check(
+            "{p; <(t)line|x}",
+            UnclosedViewElement {
+                view: "t".to_string(),
+                element: "line".to_string(),
+            },
+            sp(4, 13),
+            &["|(t)line>"],
+        );
+        // Views may overlap each other and curly tags
+        assert!(parse_document("<(t)a|<(g)b||(t)a>|(g)>").is_ok());
+        assert!(parse_document("{p; <(t)a|x}{p; y|(t)>}").is_ok());
+        assert!(parse_document("<(g)tei:s|x|(g)s>").is_ok());
+        // The same with the views written as in the README
+        assert!(parse_document("<t/a|<g/b||t/a>|/g>").is_ok());
+        assert!(parse_document("{p; <t/a|x}{p; y|t/>}").is_ok());
+        assert!(parse_document("<(t)a|x|t/a>").is_ok());
+    }
+
+    #[test]
+    fn test_render() {
+        let src = "{p;\n\tabc {- def}";
+        assert_eq!(
+            err(src).render(src),
+            concat!(
+                "error: unterminated comment\n",
+                " --> 2:6\n",
+                "  |\n",
+                "2 |  abc {- def}\n",
+                "  |      ^^\n",
+                "  = expected `-}`\n",
+            )
+        );
+        let src = "{p truex}";
+        assert_eq!(
+            CptmlError::from(err(src)).render(src),
+            concat!(
+                "error: unexpected 'x'\n",
+                " --> 1:8\n",
+                "  |\n",
+                "1 | {p truex}\n",
+                "  |        ^\n",
+                "  = expected `;` or `}` or whitespace\n",
+            )
+        );
+        assert_eq!(
+            CptmlError::NotImplemented.render(src),
+            "error: not implemented\n"
+        );
+    }
+}
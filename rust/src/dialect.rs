@@ -0,0 +1,660 @@
+// The syntax of CPTML changed between versions. A document says which version it uses with a
+// header like "{!cptml version=1}" near its start and parse_document parses the document in that
+// dialect, so its tree still encodes back to the source. migrate rewrites a document into another
+// dialect.
+//
+// Only the tokens that differ between dialects are rewritten: comments, the prefix of special
+// names, the namespace separator, "\u" escapes and unescaped "{" or "<" in text. Everything else,
+// including the inside of strings, code blocks and TeX code, is copied as is.
+
+use unicode_xid::UnicodeXID;
+
+use crate::ast::{codeblock, comment, tag_args, tex_code, xid_name};
+use crate::ast::{CommentSyntax, TagAttrValue};
+use crate::pos::{Position, Span};
+use crate::prelude::{CptmlResult, ParseError, ParseErrorKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dialect {
+    // The one in the README and example.cptml: "/* */" comments, ".id" (or "!id") special names,
+    // "ns.name" (or "ns:name") namespaces, "\uN;" escapes, views of curly tags (e.g. "{(v)tag}")
+    // and "{" or "<" between whitespace as text (e.g. "a < b")
+    V1,
+    // "{- -}" comments, "!id" special names, "ns:name" namespaces and "\u{N}" escapes. Both
+    // dialects also have the "{% %}" comments of the tree-sitter grammar.
+    V2,
+}
+
+// Dialect::LATEST, as for documents without a header
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect::LATEST
+    }
+}
+
+impl Dialect {
+    // Also the dialect of documents without a header
+    pub const LATEST: Dialect = Dialect::V2;
+
+    pub fn from_version(version: i64) -> Option<Dialect> {
+        match version {
+            1 => Some(Dialect::V1),
+            2 => Some(Dialect::V2),
+            _ => None,
+        }
+    }
+
+    pub fn version(self) -> i64 {
+        match self {
+            Dialect::V1 => 1,
+            Dialect::V2 => 2,
+        }
+    }
+
+    fn comment_delimiters(self) -> (&'static str, &'static str) {
+        match self {
+            Dialect::V1 => ("/*", "*/"),
+            Dialect::V2 => ("{-", "-}"),
+        }
+    }
+
+    // The one written when migrating to this dialect
+    fn special_prefix(self) -> char {
+        match self {
+            Dialect::V1 => '.',
+            Dialect::V2 => '!',
+        }
+    }
+
+    fn is_special_prefix(self, ch: char) -> bool {
+        match self {
+            Dialect::V1 => ch == '.' || ch == '!',
+            Dialect::V2 => ch == '!',
+        }
+    }
+
+    fn namespace_separator(self) -> char {
+        match self {
+            Dialect::V1 => '.',
+            Dialect::V2 => ':',
+        }
+    }
+
+    pub(crate) fn has_curly_views(self) -> bool {
+        self == Dialect::V1
+    }
+
+    // Whether the "{" or "<" at the start of rest is text, given the unescaped char before it
+    pub(crate) fn is_literal_delimiter(self, before: Option<char>, rest: &str) -> bool {
+        let mut chars = rest.chars();
+        self == Dialect::V1
+            && matches!(chars.next(), Some('{' | '<'))
+            && chars.next().is_some_and(char::is_whitespace)
+            && before.is_some_and(char::is_whitespace)
+    }
+
+    // Whether the "{" or "<" that is all of rest could still be text once more input comes
+    pub(crate) fn may_be_literal_delimiter(self, before: Option<char>, rest: &str) -> bool {
+        self == Dialect::V1 && matches!(rest, "{" | "<") && before.is_some_and(char::is_whitespace)
+    }
+
+    // The char and length of the "\u" escape at the start of the input, if it is a valid one
+    pub(crate) fn decode_unicode_escape(self, input: &str) -> Option<(char, usize)> {
+        let (open, close, max_digits) = match self {
+            Dialect::V1 => ("\\u", ';', usize::MAX),
+            Dialect::V2 => ("\\u{", '}', 6),
+        };
+        let rest = input.strip_prefix(open)?;
+        let n_digits = rest.find(|ch: char| !ch.is_ascii_hexdigit())?;
+        if n_digits == 0 || n_digits > max_digits || !rest[n_digits..].starts_with(close) {
+            return None;
+        }
+        let code = u32::from_str_radix(&rest[..n_digits], 16).ok()?;
+        let ch = char::from_u32(code)?;
+        Some((ch, open.len() + n_digits + close.len_utf8()))
+    }
+
+    fn encode_unicode_escape(self, ch: char) -> String {
+        match self {
+            Dialect::V1 => format!("\\u{:X};", ch as u32),
+            Dialect::V2 => format!("\\u{{{:02X}}}", ch as u32),
+        }
+    }
+}
+
+// Skips what may come before the header: a byte order mark, whitespace and the nodes that are
+// read the same in every dialect ("{% %}" comments, code blocks and TeX code), e.g. "$$ x $$\n"
+pub(crate) fn skip_preamble(input: &str) -> &str {
+    let mut rest = input.strip_prefix('\u{FEFF}').unwrap_or(input).trim_start();
+    loop {
+        let after = match (comment(rest), codeblock(rest), tex_code(rest)) {
+            (Ok((after, comment)), _, _) if comment.syntax() == CommentSyntax::Percent => after,
+            (_, Ok((after, _)), _) | (_, _, Ok((after, _))) => after,
+            _ => return rest,
+        };
+        rest = after.trim_start();
+    }
+}
+
+// The version attribute of the header, a "{!cptml ...}" or "{.cptml ...}" that is the first tag
+// of the document. Only the preamble (see skip_preamble) may come before it.
+fn version_attr(input: &str) -> Option<(TagAttrValue<'_>, Span)> {
+    let rest = skip_preamble(input);
+    let rest = ["{!cptml", "{.cptml"]
+        .iter()
+        .find_map(|head| rest.strip_prefix(head))?;
+    if !rest.starts_with(|ch: char| ch.is_whitespace() || ch == ';' || ch == '}') {
+        return None;
+    }
+    let after_name = input.len() - rest.len();
+    let (_, attrs) = tag_args(&input[after_name..]).ok()?;
+    let base = Position::new().after(&input[..after_name]);
+    attrs.into_iter().find_map(|attr| {
+        let is_version = attr.name()?.full_name() == "version";
+        let span = attr.value_span()?.rebase(base);
+        match (is_version, attr.value()) {
+            (true, Some(value)) => Some((value.clone(), span)),
+            _ => None,
+        }
+    })
+}
+
+// The dialect declared in the header of the document, which is Dialect::LATEST if there is no
+// header or if it has no version. Unknown versions are errors.
+pub fn detect_dialect(input: &str) -> CptmlResult<Dialect> {
+    let (value, span) = match version_attr(input) {
+        Some(attr) => attr,
+        None => return Ok(Dialect::LATEST),
+    };
+    let dialect = match value {
+        TagAttrValue::Integer(_, version) => Dialect::from_version(version),
+        _ => None,
+    };
+    match dialect {
+        Some(dialect) => Ok(dialect),
+        None => {
+            let kind = ParseErrorKind::UnknownVersion(value.encode_cptml());
+            Err(ParseError::new(kind, span).into())
+        }
+    }
+}
+
+struct Migration<'s> {
+    input: &'s str,
+    from: Dialect,
+    to: Dialect,
+    // Where the rest of the input starts
+    at: usize,
+    pos: Position,
+    out: String,
+    // Where the value of "version=N" in the header starts and ends
+    version_at: Option<(usize, usize)>,
+}
+
+impl<'s> Migration<'s> {
+    fn rest(&self) -> &'s str {
+        &self.input[self.at..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.rest().chars().nth(1)
+    }
+
+    fn copy(&mut self, len: usize) {
+        let text = &self.input[self.at..self.at + len];
+        self.out.push_str(text);
+        self.pos = self.pos.after(text);
+        self.at += len;
+    }
+
+    fn copy_char(&mut self) {
+        self.copy(self.peek().map_or(0, char::len_utf8));
+    }
+
+    fn replace(&mut self, len: usize, with: &str) {
+        self.out.push_str(with);
+        self.pos = self.pos.after(&self.input[self.at..self.at + len]);
+        self.at += len;
+    }
+
+    fn not_migratable(&self, len: usize, reason: String) -> ParseError {
+        let end = self.pos.after(&self.input[self.at..self.at + len]);
+        let kind = ParseErrorKind::NotMigratable(reason);
+        ParseError::new(kind, Span::new_from_to(self.pos, end))
+    }
+
+    // "{% %}" comments are the same in every dialect
+    fn percent_comment(&mut self) -> bool {
+        let len = match comment(self.rest()) {
+            Ok((after, found)) if found.syntax() == CommentSyntax::Percent => {
+                self.rest().len() - after.len()
+            }
+            _ => return false,
+        };
+        self.copy(len);
+        true
+    }
+
+    // E.g. "\u{E7}" -> "\uE7;". Other escapes are the same in every dialect.
+    fn escape(&mut self) {
+        if let Some((ch, len)) = self.from.decode_unicode_escape(self.rest()) {
+            let escape = self.to.encode_unicode_escape(ch);
+            self.replace(len, &escape);
+            return;
+        }
+        let escaped_len = self.rest()[1..].chars().next().map_or(0, char::len_utf8);
+        self.copy(1 + escaped_len);
+    }
+
+    fn comment(&mut self) -> Result<(), ParseError> {
+        let (from_start, from_end) = self.from.comment_delimiters();
+        let (to_start, to_end) = self.to.comment_delimiters();
+        let mut depth = 0;
+        while !self.rest().is_empty() {
+            if self.rest().starts_with(from_start) {
+                depth += 1;
+                self.replace(from_start.len(), to_start);
+            } else if self.rest().starts_with(from_end) {
+                depth -= 1;
+                self.replace(from_end.len(), to_end);
+                if depth == 0 {
+                    return Ok(());
+                }
+            } else if self.from != self.to {
+                for delimiter in [to_start, to_end] {
+                    if self.rest().starts_with(delimiter) {
+                        let reason = format!("the comment contains {:?}", delimiter);
+                        return Err(self.not_migratable(delimiter.len(), reason));
+                    }
+                }
+                self.copy_char();
+            } else {
+                self.copy_char();
+            }
+        }
+        // Left for the parser to complain about
+        Ok(())
+    }
+
+    // E.g. "(t)ns.line" -> "(t)ns:line", "t/ns.line" -> "t/ns:line" or ".id" -> "!id"
+    fn name(&mut self) {
+        if self.peek() == Some('(') {
+            let view_len = self.rest().find(')').map_or(1, |pos| pos + 1);
+            self.copy(view_len);
+        } else if let Ok((after_view, view)) = xid_name(self.rest()) {
+            if after_view.starts_with('/') {
+                self.copy(view.len() + 1);
+            }
+        }
+        match (self.peek(), self.peek_second()) {
+            (Some(prefix), Some(first)) if self.from.is_special_prefix(prefix) => {
+                if UnicodeXID::is_xid_start(first) {
+                    self.replace(1, &self.to.special_prefix().to_string());
+                }
+                return;
+            }
+            _ => {}
+        }
+        let name_len = match xid_name(self.rest()) {
+            Ok((_, name)) => name.len(),
+            Err(_) => return,
+        };
+        self.copy(name_len);
+        let separator = self.from.namespace_separator();
+        if self.peek() == Some(separator) && self.peek_second().is_some_and(|ch| ch.is_xid_start())
+        {
+            self.replace(1, &self.to.namespace_separator().to_string());
+        }
+    }
+
+    fn string(&mut self) {
+        self.copy(1);
+        while let Some(ch) = self.peek() {
+            match ch {
+                '\\' => self.escape(),
+                '"' => return self.copy(1),
+                _ => self.copy_char(),
+            }
+        }
+    }
+
+    // After the "{" or "<" of a tag, until the ";", "}" or "|" that ends its head
+    fn tag_head(&mut self) -> Result<(), ParseError> {
+        self.name();
+        let mut depth = 0;
+        while let Some(ch) = self.peek() {
+            if let Some((start, end)) = self.version_at.filter(|&(start, _)| start == self.at) {
+                self.replace(end - start, &self.to.version().to_string());
+                continue;
+            }
+            if self.rest().starts_with(self.from.comment_delimiters().0) {
+                self.comment()?;
+                self.name();
+                continue;
+            }
+            if self.percent_comment() {
+                self.name();
+                continue;
+            }
+            match ch {
+                ';' | '}' | '|' if depth == 0 => return Ok(()),
+                '"' => self.string(),
+                '<' if self.rest().starts_with("<>") => {
+                    self.copy(2);
+                    self.content(Some("</>"))?;
+                    self.copy(self.rest().len().min("</>".len()));
+                }
+                '<' => {
+                    let url_len = self.rest().find('>').map_or(1, |pos| pos + 1);
+                    self.copy(url_len);
+                }
+                '[' | '{' => {
+                    depth += 1;
+                    self.copy(1);
+                }
+                ']' | '}' => {
+                    depth -= 1;
+                    self.copy(1);
+                }
+                _ if ch.is_whitespace() => {
+                    self.copy_char();
+                    if depth == 0 {
+                        self.name();
+                    }
+                }
+                _ => self.copy_char(),
+            }
+        }
+        Ok(())
+    }
+
+    // Copies the code block or TeX code at the start of the input, if there is one
+    fn fenced(&mut self) {
+        let rest = self.rest();
+        let len = match codeblock(rest) {
+            Ok((after, _)) => rest.len() - after.len(),
+            Err(_) => match tex_code(rest) {
+                Ok((after, _)) => rest.len() - after.len(),
+                Err(_) => 1,
+            },
+        };
+        self.copy(len);
+    }
+
+    // Nodes until the end of the input or until the end of the enclosing tag or value
+    fn content(&mut self, end: Option<&str>) -> Result<(), ParseError> {
+        while let Some(ch) = self.peek() {
+            let rest = self.rest();
+            if end.is_some_and(|end| rest.starts_with(end)) {
+                return Ok(());
+            }
+            if rest.starts_with(self.from.comment_delimiters().0) {
+                self.comment()?;
+                continue;
+            }
+            if self.percent_comment() {
+                continue;
+            }
+            // E.g. the "<" in "a < b" has to be escaped in V2
+            let before = self.input[..self.at].chars().next_back();
+            if self.from.is_literal_delimiter(before, rest) {
+                match self.to.is_literal_delimiter(before, rest) {
+                    true => self.copy(1),
+                    false => self.replace(1, &format!("\\{}", ch)),
+                }
+                continue;
+            }
+            // E.g. the "/*" in the text "a/*b" has to be escaped in V1
+            if rest.starts_with(self.to.comment_delimiters().0) {
+                let escape = self.to.encode_unicode_escape(ch);
+                self.replace(ch.len_utf8(), &escape);
+                continue;
+            }
+            match ch {
+                '\\' => self.escape(),
+                '`' | '$' => self.fenced(),
+                '{' => {
+                    self.copy(1);
+                    let views_lost = self.from.has_curly_views() && !self.to.has_curly_views();
+                    if views_lost && self.peek() == Some('(') {
+                        let view_len = self.rest().find(')').map_or(1, |pos| pos + 1);
+                        let reason =
+                            format!("the curly tag has the view {}", &rest[1..1 + view_len]);
+                        return Err(self.not_migratable(view_len, reason));
+                    }
+                    self.tag_head()?;
+                    if self.peek() == Some(';') {
+                        self.copy(1);
+                        self.content(Some("}"))?;
+                    }
+                    if self.peek() == Some('}') {
+                        self.copy(1);
+                    }
+                }
+                '<' => {
+                    self.copy(1);
+                    self.tag_head()?;
+                    if self.peek() == Some('|') {
+                        self.copy(1);
+                    }
+                }
+                '|' => {
+                    self.copy(1);
+                    self.name();
+                }
+                _ => self.copy_char(),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Rewrites a document from one dialect to another, including the version in its header. Fails if
+// something can't be written in the other dialect, e.g. a "-}" inside a V1 comment.
+pub fn migrate(input: &str, from: Dialect, to: Dialect) -> CptmlResult<String> {
+    let header = version_attr(input);
+    let mut migration = Migration {
+        input: input,
+        from: from,
+        to: to,
+        at: 0,
+        pos: Position::new(),
+        out: String::new(),
+        version_at: header
+            .as_ref()
+            .filter(|(value, _)| matches!(value, TagAttrValue::Integer(..)))
+            .map(|(_, span)| (span.start.byte, span.end.byte)),
+    };
+    // Without a header, the document would be taken as Dialect::LATEST
+    if header.is_none() && to != Dialect::LATEST {
+        let header = format!(
+            "{{{}cptml version={}}}\n",
+            to.special_prefix(),
+            to.version()
+        );
+        migration.replace(0, &header);
+    }
+    migration.content(None)?;
+    Ok(migration.out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{parse_document, Node};
+    use crate::dialect::*;
+    use crate::prelude::CptmlError;
+
+    #[test]
+    fn test_detect_dialect() {
+        assert_eq!(detect_dialect("{p; a}"), Ok(Dialect::LATEST));
+        assert_eq!(detect_dialect("{!cptml version=2}"), Ok(Dialect::V2));
+        assert_eq!(detect_dialect("{!cptml}{p; a}"), Ok(Dialect::LATEST));
+        assert_eq!(detect_dialect("\n{.cptml version=1;}"), Ok(Dialect::V1));
+        assert_eq!(detect_dialect("{!cptmlx version=1}"), Ok(Dialect::LATEST));
+        let example = include_str!("../../example.cptml");
+        assert_eq!(detect_dialect(example), Ok(Dialect::V1));
+
+        assert_eq!(
+            detect_dialect("\u{FEFF} \n{!cptml version=1}"),
+            Ok(Dialect::V1)
+        );
+        // Only nodes that are read the same in every dialect may come before the header
+        assert_eq!(
+            detect_dialect("$$ x $$\n{% a %} ``b`` {.cptml version=1}"),
+            Ok(Dialect::V1)
+        );
+        assert_eq!(
+            detect_dialect("{- a -} {!cptml version=1}"),
+            Ok(Dialect::LATEST)
+        );
+
+        // Anywhere else, it is an ordinary tag
+        assert_eq!(detect_dialect("{p}{!cptml version=9}"), Ok(Dialect::LATEST));
+        assert_eq!(detect_dialect("a {!cptml version=9}"), Ok(Dialect::LATEST));
+        assert_eq!(
+            detect_dialect("$x$ b {!cptml version=9}"),
+            Ok(Dialect::LATEST)
+        );
+        assert_eq!(detect_dialect("`{!cptml version=3}`"), Ok(Dialect::LATEST));
+        assert!(parse_document("`{!cptml version=3}`").is_ok());
+        assert!(parse_document("{- see {!cptml version=1} -}{p; a}").is_ok());
+        assert!(parse_document("\\{!cptml version=1\\}").is_ok());
+
+        let err = match detect_dialect(" {!cptml version=9}") {
+            Err(CptmlError::Parse(err)) => err,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(err.kind(), &ParseErrorKind::UnknownVersion("9".to_string()));
+        assert_eq!(err.span(), Span::new2(17, 1, 17, 18, 1, 18));
+        assert!(detect_dialect("{!cptml version=\"1\"}").is_err());
+        assert!(parse_document("{!cptml version=3}").is_err());
+    }
+
+    #[test]
+    fn test_migrate() {
+        let v1 = concat!(
+            "{.cptml version=1}\n",
+            "{p .id=\"a\\u2014;b\" ns.x=[.5 {k: <>{b; \\uE7;}</>}]; /* c /* d */ */\n",
+            "  a/b \\uE7; ``/* \\uE7; */`` <(t)ns.line .n=1|x|(t)ns.line>}",
+        );
+        let v2 = concat!(
+            "{!cptml version=2}\n",
+            "{p !id=\"a\\u{2014}b\" ns:x=[.5 {k: <>{b; \\u{E7}}</>}]; {- c {- d -} -}\n",
+            "  a/b \\u{E7} ``/* \\uE7; */`` <(t)ns:line !n=1|x|(t)ns:line>}",
+        );
+        assert_eq!(migrate(v1, Dialect::V1, Dialect::V2), Ok(v2.to_string()));
+        assert_eq!(migrate(v2, Dialect::V2, Dialect::V1), Ok(v1.to_string()));
+        assert_eq!(migrate(v2, Dialect::V2, Dialect::V2), Ok(v2.to_string()));
+
+        // Without a header, V1 documents need one
+        assert_eq!(
+            migrate("{p; a/*b}", Dialect::V2, Dialect::V1),
+            Ok("{.cptml version=1}\n{p; a\\u2F;*b}".to_string())
+        );
+        assert_eq!(
+            migrate("<t/ns.l .n=1|x|t/ns.l>|/t>", Dialect::V1, Dialect::V2),
+            Ok("<t/ns:l !n=1|x|t/ns:l>|/t>".to_string())
+        );
+        assert_eq!(
+            migrate("{p; {!b}}", Dialect::V1, Dialect::V2),
+            Ok("{p; {!b}}".to_string())
+        );
+        let err = match migrate("/* a -} */", Dialect::V1, Dialect::V2) {
+            Err(CptmlError::Parse(err)) => err,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(err.span(), Span::new2(5, 1, 5, 7, 1, 7));
+
+        // The whole value of the version is replaced, however it is written
+        assert_eq!(
+            migrate("{.cptml version=+1}\n{p; a}", Dialect::V1, Dialect::V2),
+            Ok("{!cptml version=2}\n{p; a}".to_string())
+        );
+        assert!(parse_document("{!cptml version=+1}\n{p; a}").is_ok());
+
+        // "{% %}" comments are the same in both dialects but "{" and "<" need escapes in V2
+        let v1 = "{.cptml version=1}\n{p; a < b {% c; } %}}";
+        let v2 = "{!cptml version=2}\n{p; a \\< b {% c; } %}}";
+        assert_eq!(migrate(v1, Dialect::V1, Dialect::V2), Ok(v2.to_string()));
+        assert!(parse_document(v1).is_ok() && parse_document(v2).is_ok());
+        let err = match migrate("{p; {(v)b}}", Dialect::V1, Dialect::V2) {
+            Err(CptmlError::Parse(err)) => err,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(
+            err.to_string(),
+            "1:6: cannot migrate: the curly tag has the view (v)"
+        );
+    }
+
+    #[test]
+    fn test_parse_version_1() {
+        let v1 = "{.cptml version=1}\n{p .id=\"\\uE7;\" ns.x=1; /* c */ x { y}";
+        let doc = parse_document(v1).unwrap();
+        assert_eq!(doc.encode_cptml(), v1);
+        let p = match &doc.nodes()[2] {
+            Node::CurlyTag(tag) => tag,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(p.span(), Span::new2(19, 2, 0, 56, 2, 37));
+        assert_eq!(p.content()[1].span(), Span::new2(42, 2, 23, 49, 2, 30));
+        let args = p.start().args();
+        assert_eq!(args.as_slice()[0].name().unwrap().encode_cptml(), ".id");
+        assert_eq!(args.as_slice()[0].name().unwrap().full_name(), "!id");
+        assert_eq!(
+            args.get("!id"),
+            Ok(Some(&TagAttrValue::String(
+                "\"\\uE7;\"".into(),
+                "ç".to_string()
+            )))
+        );
+        assert!(args.contains("ns:x"));
+        // A special name on its own is not a decimal like ".5"
+        let doc = parse_document("{.cptml version=1}{p .hidden .5}").unwrap();
+        match &doc.nodes()[1] {
+            Node::CurlyTag(tag) => assert!(tag.start().args().contains("!hidden")),
+            other => panic!("{:?}", other),
+        }
+        match &p.content()[1] {
+            Node::Comment(comment) => assert_eq!(comment.syntax(), CommentSyntax::Slash),
+            other => panic!("{:?}", other),
+        }
+        match &p.content()[2] {
+            Node::Text(text) => assert_eq!(text.meaning(), " x { y"),
+            other => panic!("{:?}", other),
+        }
+
+        // Views of curly tags and "{% %}" comments
+        let v1 = "{!cptml version=1}\n{(v)ns:tag; a {% b {% c %} %}}";
+        let doc = parse_document(v1).unwrap();
+        assert_eq!(doc.encode_cptml(), v1);
+        match &doc.nodes()[2] {
+            Node::CurlyTag(tag) => assert_eq!(tag.start().view(), "v"),
+            other => panic!("{:?}", other),
+        }
+        assert!(parse_document("{p; a {% b %}}").is_ok());
+        assert!(parse_document("{p; a /* b */}").is_ok());
+        assert!(parse_document("{(v)p; a}").is_err());
+        assert!(parse_document("{p; a { b}").is_err());
+        assert!(parse_document("{.cptml version=1}\n{p; a {- b -}}").is_err());
+
+        let err = match parse_document("{.cptml version=1}\n{p; /* c */ \\q}") {
+            Err(CptmlError::Parse(err)) => err,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(err.kind(), &ParseErrorKind::BadEscape("\\q".to_string()));
+        assert_eq!(err.span(), Span::new2(31, 2, 12, 33, 2, 14));
+        let err = match parse_document("{.cptml version=1}\n{p; \\u{E7}}") {
+            Err(CptmlError::Parse(err)) => err,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(
+            err.kind(),
+            &ParseErrorKind::OnlyInDialect("\\u{E7}".to_string(), Dialect::V2)
+        );
+    }
+}
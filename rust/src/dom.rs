@@ -0,0 +1,824 @@
+// A tree of the curly tags of a document that can be walked in every direction. The nodes live in
+// an arena and are referred to by NodeId, so parents, siblings and children are cheap to reach.
+// Each node keeps its syntax from ast.rs, so the tree can be encoded back into the exact source.
+// The tree can be edited with pieces of CPTML source, and encode_cptml only differs from the
+// original source where the tree was changed.
+//
+// Pointy tags don't split the tree: their starts and ends are milestones among the other children
+// of the curly tag they are in.
+
+use crate::ast::{self, CodeBlock, Comment, CurlyTagStart, ErrorNode, IdFullName, InlineText};
+use crate::ast::{ParseOptions, PointyTagEnd, PointyTagStart, Rebase, TexCode};
+use crate::attrs::AttrMap;
+use crate::dialect::Dialect;
+use crate::pos::Span;
+use crate::prelude::{CptmlError, CptmlResult};
+
+fn not_an_element(kind: &NodeKind) -> CptmlError {
+    CptmlError::InvalidArgument(format!("{:?} is not an element", kind))
+}
+
+// Only meaningful for the document it came from. Using it with another one may panic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+// A curly tag. Its content is in the children of its node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element<'a> {
+    start: CurlyTagStart<'a>,
+    // False for tags like "{br}", which are not the same as "{br;}"
+    has_content: bool,
+    // See ast::CurlyTag::is_unterminated
+    unterminated: bool,
+}
+
+impl<'a> Element<'a> {
+    // A tag with content, e.g. "{b;...}"
+    pub(crate) fn new(start: CurlyTagStart<'a>) -> Element<'a> {
+        Element {
+            start: start,
+            has_content: true,
+            unterminated: false,
+        }
+    }
+
+    pub fn into_owned(self) -> Element<'static> {
+        Element {
+            start: self.start.into_owned(),
+            has_content: self.has_content,
+            unterminated: self.unterminated,
+        }
+    }
+
+    pub fn name(&self) -> &IdFullName<'a> {
+        self.start.element()
+    }
+
+    pub fn attrs(&self) -> &AttrMap<'a> {
+        self.start.args()
+    }
+
+    pub fn start(&self) -> &CurlyTagStart<'a> {
+        &self.start
+    }
+
+    pub fn has_content(&self) -> bool {
+        self.has_content
+    }
+
+    pub fn is_unterminated(&self) -> bool {
+        self.unterminated
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind<'a> {
+    // The parent of the top-level nodes
+    Root,
+    Element(Element<'a>),
+    Text(InlineText<'a>),
+    Comment(Comment<'a>),
+    CodeBlock(CodeBlock<'a>),
+    Math(TexCode<'a>),
+    ViewStart(PointyTagStart<'a>),
+    ViewEnd(PointyTagEnd<'a>),
+    // Only in documents from parse_document_recovering
+    Error(ErrorNode<'a>),
+}
+
+impl<'a> NodeKind<'a> {
+    pub fn into_owned(self) -> NodeKind<'static> {
+        match self {
+            NodeKind::Root => NodeKind::Root,
+            NodeKind::Element(element) => NodeKind::Element(element.into_owned()),
+            NodeKind::Text(text) => NodeKind::Text(text.into_owned()),
+            NodeKind::Comment(comment) => NodeKind::Comment(comment.into_owned()),
+            NodeKind::CodeBlock(code) => NodeKind::CodeBlock(code.into_owned()),
+            NodeKind::Math(code) => NodeKind::Math(code.into_owned()),
+            NodeKind::ViewStart(tag) => NodeKind::ViewStart(tag.into_owned()),
+            NodeKind::ViewEnd(tag) => NodeKind::ViewEnd(tag.into_owned()),
+            NodeKind::Error(error) => NodeKind::Error(error.into_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct NodeData<'a> {
+    kind: NodeKind<'a>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document<'a> {
+    // The root is always the first node
+    nodes: Vec<NodeData<'a>>,
+    // The inserted pieces of CPTML are parsed like the document was
+    dialect: Dialect,
+    options: ParseOptions,
+}
+
+impl<'a> Document<'a> {
+    pub fn new(doc: ast::Document<'a>) -> Document<'a> {
+        Document::new_with(doc, &ParseOptions::default())
+    }
+
+    // The options are the ones the document was parsed with
+    pub fn new_with(doc: ast::Document<'a>, options: &ParseOptions) -> Document<'a> {
+        let dialect = doc.dialect();
+        let nodes = doc.into_nodes();
+        let span = match (nodes.first(), nodes.last()) {
+            (Some(first), Some(last)) => Span::new_from_to(first.span().start, last.span().end),
+            _ => Span::new(),
+        };
+        let mut ans = Document::empty(span, dialect, options);
+        let root = ans.root();
+        ans.add_nodes(root, nodes);
+        ans
+    }
+
+    fn add_nodes(&mut self, parent: NodeId, nodes: Vec<ast::Node<'a>>) {
+        for node in nodes {
+            let id = self.add_node(node);
+            self.nodes[id.0].parent = Some(parent);
+            self.nodes[parent.0].children.push(id);
+        }
+    }
+
+    // Adds the node and everything under it without a parent
+    fn add_node(&mut self, node: ast::Node<'a>) -> NodeId {
+        let span = node.span();
+        let (kind, content) = match node {
+            ast::Node::CurlyTag(tag) => {
+                let unterminated = tag.is_unterminated();
+                let (start, content) = tag.into_parts();
+                let element = Element {
+                    start: start,
+                    has_content: content.is_some(),
+                    unterminated: unterminated,
+                };
+                (NodeKind::Element(element), content.unwrap_or_default())
+            }
+            ast::Node::PointyTagStart(tag) => (NodeKind::ViewStart(tag), Vec::new()),
+            ast::Node::PointyTagEnd(tag) => (NodeKind::ViewEnd(tag), Vec::new()),
+            ast::Node::Text(text) => (NodeKind::Text(text), Vec::new()),
+            ast::Node::Comment(comment) => (NodeKind::Comment(comment), Vec::new()),
+            ast::Node::CodeBlock(code) => (NodeKind::CodeBlock(code), Vec::new()),
+            ast::Node::TexCode(code) => (NodeKind::Math(code), Vec::new()),
+            ast::Node::Error(error) => (NodeKind::Error(error), Vec::new()),
+        };
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(NodeData {
+            kind: kind,
+            parent: None,
+            children: Vec::new(),
+            span: span,
+        });
+        self.add_nodes(id, content);
+        id
+    }
+
+    pub fn into_owned(self) -> Document<'static> {
+        Document {
+            nodes: self
+                .nodes
+                .into_iter()
+                .map(|node| NodeData {
+                    kind: node.kind.into_owned(),
+                    parent: node.parent,
+                    children: node.children,
+                    span: node.span,
+                })
+                .collect(),
+            dialect: self.dialect,
+            options: self.options,
+        }
+    }
+
+    // A document with nothing but the root
+    pub(crate) fn empty(span: Span, dialect: Dialect, options: &ParseOptions) -> Document<'a> {
+        Document {
+            nodes: vec![NodeData {
+                kind: NodeKind::Root,
+                parent: None,
+                children: Vec::new(),
+                span: span,
+            }],
+            dialect: dialect,
+            options: options.clone(),
+        }
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    pub(crate) fn options(&self) -> &ParseOptions {
+        &self.options
+    }
+
+    // The nodes of a piece of CPTML, e.g. "{b; x}" or "some text", with empty spans. The piece
+    // goes inside the elements from parent up, which count towards the nesting limit.
+    fn parse_fragment(&self, src: &str, parent: NodeId) -> CptmlResult<Vec<ast::Node<'static>>> {
+        let depth = std::iter::once(parent)
+            .chain(self.ancestors(parent))
+            .filter(|&id| matches!(self.kind(id), NodeKind::Element(_)))
+            .count();
+        let nodes = ast::parse_fragment(src, self.dialect, &self.options, depth)?;
+        let mut nodes: Vec<ast::Node<'static>> =
+            nodes.into_iter().map(ast::Node::into_owned).collect();
+        nodes.map_spans(&|_| Span::new());
+        Ok(nodes)
+    }
+
+    fn parse_text(&self, src: &str) -> CptmlResult<InlineText<'static>> {
+        match self.parse_fragment(src, self.root())?.as_mut_slice() {
+            [ast::Node::Text(text)] => Ok(std::mem::take(text)),
+            _ => Err(CptmlError::InvalidArgument(format!(
+                "{:?} is not text on its own",
+                src
+            ))),
+        }
+    }
+
+    // Adds a node as the last child of parent, which must be the root or an element
+    pub(crate) fn push_child(&mut self, parent: NodeId, kind: NodeKind<'a>, span: Span) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(NodeData {
+            kind: kind,
+            parent: None,
+            children: Vec::new(),
+            span: span,
+        });
+        self.attach(id, parent, self.children(parent).len());
+        id
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    pub fn kind(&self, id: NodeId) -> &NodeKind<'a> {
+        &self.nodes[id.0].kind
+    }
+
+    pub fn span(&self, id: NodeId) -> Span {
+        self.nodes[id.0].span
+    }
+
+    // None for the root
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].parent
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.nodes[id.0].children
+    }
+
+    pub fn child(&self, id: NodeId, idx: usize) -> Option<NodeId> {
+        self.children(id).get(idx).copied()
+    }
+
+    pub fn first_child(&self, id: NodeId) -> Option<NodeId> {
+        self.children(id).first().copied()
+    }
+
+    pub fn last_child(&self, id: NodeId) -> Option<NodeId> {
+        self.children(id).last().copied()
+    }
+
+    // Where the node is among the children of its parent
+    pub fn index(&self, id: NodeId) -> Option<usize> {
+        let parent = self.parent(id)?;
+        self.children(parent).iter().position(|&child| child == id)
+    }
+
+    pub fn previous_sibling(&self, id: NodeId) -> Option<NodeId> {
+        let idx = self.index(id)?.checked_sub(1)?;
+        self.child(self.parent(id)?, idx)
+    }
+
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        let idx = self.index(id)?;
+        self.child(self.parent(id)?, idx + 1)
+    }
+
+    // From the parent of the node up to the root
+    pub fn ancestors(&self, id: NodeId) -> Ancestors<'_, 'a> {
+        Ancestors {
+            doc: self,
+            next: self.parent(id),
+        }
+    }
+
+    // Every node under this one (but not itself) in document order
+    pub fn descendants(&self, id: NodeId) -> Descendants<'_, 'a> {
+        let mut stack = self.children(id).to_vec();
+        stack.reverse();
+        Descendants {
+            doc: self,
+            stack: stack,
+        }
+    }
+
+    // The decoded text of every text node under this one
+    pub fn text(&self, id: NodeId) -> String {
+        let mut ans = String::new();
+        for node in std::iter::once(id).chain(self.descendants(id)) {
+            if let NodeKind::Text(text) = self.kind(node) {
+                ans.push_str(text.meaning());
+            }
+        }
+        ans
+    }
+
+    fn element_mut(&mut self, id: NodeId) -> CptmlResult<&mut Element<'a>> {
+        match &mut self.nodes[id.0].kind {
+            NodeKind::Element(element) => Ok(element),
+            other => Err(not_an_element(other)),
+        }
+    }
+
+    fn detach_node(&mut self, id: NodeId) {
+        if let Some(parent) = self.nodes[id.0].parent.take() {
+            self.nodes[parent.0].children.retain(|&child| child != id);
+        }
+    }
+
+    // Whether nodes can be inserted at the index of the children of parent
+    fn check_parent(&self, parent: NodeId, idx: usize) -> CptmlResult<()> {
+        match self.kind(parent) {
+            NodeKind::Root | NodeKind::Element(_) => {}
+            other => return Err(not_an_element(other)),
+        }
+        if idx > self.children(parent).len() {
+            let msg = format!("{:?} has no child #{}", parent, idx);
+            return Err(CptmlError::InvalidArgument(msg));
+        }
+        Ok(())
+    }
+
+    // See check_parent
+    fn attach(&mut self, id: NodeId, parent: NodeId, idx: usize) {
+        // E.g. "{br}" becomes "{br;...}"
+        if let NodeKind::Element(element) = &mut self.nodes[parent.0].kind {
+            element.has_content = true;
+        }
+        self.nodes[parent.0].children.insert(idx, id);
+        self.nodes[id.0].parent = Some(parent);
+    }
+
+    // Parses the source and inserts its nodes as children of parent, starting at idx. Inserted
+    // nodes have empty spans since they are not in the original source.
+    pub fn insert_cptml(
+        &mut self,
+        parent: NodeId,
+        idx: usize,
+        src: &str,
+    ) -> CptmlResult<Vec<NodeId>> {
+        self.check_parent(parent, idx)?;
+        let nodes = self.parse_fragment(src, parent)?;
+        let ids: Vec<NodeId> = nodes.into_iter().map(|node| self.add_node(node)).collect();
+        for (pos, &id) in ids.iter().enumerate() {
+            self.attach(id, parent, idx + pos);
+        }
+        Ok(ids)
+    }
+
+    pub fn append_cptml(&mut self, parent: NodeId, src: &str) -> CptmlResult<Vec<NodeId>> {
+        let idx = self.children(parent).len();
+        self.insert_cptml(parent, idx, src)
+    }
+
+    // Takes the node and everything under it out of the tree. The NodeId stays valid, so the node
+    // can be put back with move_node.
+    pub fn remove(&mut self, id: NodeId) -> CptmlResult<()> {
+        if id == self.root() {
+            return Err(CptmlError::InvalidArgument(
+                "the root can't be removed".to_string(),
+            ));
+        }
+        self.detach_node(id);
+        Ok(())
+    }
+
+    // Makes the node the child #idx of parent. The index is the one after the node is taken out
+    // of where it was.
+    pub fn move_node(&mut self, id: NodeId, parent: NodeId, idx: usize) -> CptmlResult<()> {
+        if id == self.root() || id == parent || self.ancestors(parent).any(|node| node == id) {
+            let msg = format!("{:?} can't be moved into {:?}", id, parent);
+            return Err(CptmlError::InvalidArgument(msg));
+        }
+        let moved_out = usize::from(self.parent(id) == Some(parent));
+        self.check_parent(parent, 0)?;
+        if idx + moved_out > self.children(parent).len() {
+            let msg = format!("{:?} has no child #{}", parent, idx);
+            return Err(CptmlError::InvalidArgument(msg));
+        }
+        self.detach_node(id);
+        self.attach(id, parent, idx);
+        Ok(())
+    }
+
+    // Puts the siblings from first to last (inclusive) inside a new element, e.g. "{b}" or
+    // "{a href=<x.com>}", which takes their place
+    pub fn wrap(&mut self, first: NodeId, last: NodeId, tag: &str) -> CptmlResult<NodeId> {
+        let parent = self.parent(first);
+        let range = match (parent, self.index(first), self.index(last)) {
+            (Some(_), Some(start), Some(end)) if parent == self.parent(last) && start <= end => {
+                start..end + 1
+            }
+            _ => {
+                let msg = format!("{:?} and {:?} are not siblings in order", first, last);
+                return Err(CptmlError::InvalidArgument(msg));
+            }
+        };
+        let parent = parent.unwrap_or_else(|| self.root());
+        let start = match self.parse_fragment(tag, parent)?.as_slice() {
+            [ast::Node::CurlyTag(tag)] if tag.content().is_empty() => tag.start().clone(),
+            _ => {
+                let msg = format!("{:?} is not a single tag without content", tag);
+                return Err(CptmlError::InvalidArgument(msg));
+            }
+        };
+        let wrapper = NodeId(self.nodes.len());
+        let children: Vec<NodeId> = self.nodes[parent.0].children.drain(range.clone()).collect();
+        for &child in &children {
+            self.nodes[child.0].parent = Some(wrapper);
+        }
+        self.nodes.push(NodeData {
+            kind: NodeKind::Element(Element {
+                start: start,
+                has_content: true,
+                unterminated: false,
+            }),
+            parent: Some(parent),
+            children: children,
+            span: Span::new(),
+        });
+        self.nodes[parent.0].children.insert(range.start, wrapper);
+        Ok(wrapper)
+    }
+
+    // Splits a text node at a byte of its source, so that part of it can be wrapped. Returns the
+    // node with the second part. Fails if either part is not valid text on its own, e.g. when the
+    // split is inside an escape sequence.
+    pub fn split_text(&mut self, id: NodeId, at: usize) -> CptmlResult<NodeId> {
+        let (src, span) = match self.kind(id) {
+            NodeKind::Text(text) => (text.src().to_string(), text.span()),
+            other => {
+                let msg = format!("{:?} is not text", other);
+                return Err(CptmlError::InvalidArgument(msg));
+            }
+        };
+        let (first_src, second_src) = match (src.get(..at), src.get(at..)) {
+            (Some(first), Some(second)) if !first.is_empty() && !second.is_empty() => {
+                (first, second)
+            }
+            _ => {
+                let msg = format!("{} is not inside of {:?}", at, src);
+                return Err(CptmlError::InvalidArgument(msg));
+            }
+        };
+        let first = self.parse_text(first_src)?;
+        let second = self.parse_text(second_src)?;
+        let middle = span.start.after(first_src);
+        self.nodes[id.0].kind = NodeKind::Text(first);
+        self.nodes[id.0].span = Span::new_from_to(span.start, middle);
+        let new_id = NodeId(self.nodes.len());
+        self.nodes.push(NodeData {
+            kind: NodeKind::Text(second),
+            parent: None,
+            children: Vec::new(),
+            span: Span::new_from_to(middle, span.end),
+        });
+        if let (Some(parent), Some(idx)) = (self.parent(id), self.index(id)) {
+            self.attach(new_id, parent, idx + 1);
+        }
+        Ok(new_id)
+    }
+
+    // Sets the value of an attribute from its source, e.g. "\"x\"" or "[1, 2]". The first
+    // attribute with the name keeps its place and the whitespace before it, and any repetitions
+    // are removed. New attributes go after the others.
+    pub fn set_attr(&mut self, id: NodeId, name: &str, value: &str) -> CptmlResult<()> {
+        // The "{x" stands for the element
+        let parent = self.parent(id).unwrap_or_else(|| self.root());
+        let src = format!("{{x {}={}}}", name, value);
+        let attr = match self.parse_fragment(&src, parent)?.as_slice() {
+            [ast::Node::CurlyTag(tag)] if tag.start().args().len() == 1 => {
+                tag.start().args().as_slice()[0].clone()
+            }
+            _ => {
+                let msg = format!("{:?} is not a single attribute value", value);
+                return Err(CptmlError::InvalidArgument(msg));
+            }
+        };
+        // E.g. "!id" for ".id" in a version 1 document
+        let name = attr.name().map(|name| name.full_name()).unwrap_or_default();
+        let attrs = self.element_mut(id)?.start.args_mut().attrs_mut();
+        let mut found = None;
+        let mut pos = 0;
+        attrs.retain(|old| {
+            pos += 1;
+            let same_name = old.name().is_some_and(|old| old.full_name() == name);
+            match (same_name, found) {
+                (true, None) => {
+                    found = Some(pos - 1);
+                    true
+                }
+                (true, Some(_)) => false,
+                (false, _) => true,
+            }
+        });
+        match found {
+            Some(pos) => {
+                let whitespace = attrs[pos].whitespace().to_string();
+                attrs[pos] = attr.with_whitespace(whitespace.into());
+            }
+            None => attrs.push(attr),
+        }
+        Ok(())
+    }
+
+    // Removes every attribute with the name, with the whitespace before it. Returns whether there
+    // was one.
+    pub fn remove_attr(&mut self, id: NodeId, name: &str) -> CptmlResult<bool> {
+        let attrs = self.element_mut(id)?.start.args_mut().attrs_mut();
+        let len = attrs.len();
+        attrs
+            .retain(|attr| attr.name().map(|attr_name| attr_name.full_name()) != Some(name.into()));
+        Ok(attrs.len() != len)
+    }
+
+    // The source of the node and of everything under it
+    pub fn encode_node(&self, id: NodeId) -> String {
+        let mut ans = String::new();
+        self.encode_into(id, &mut ans);
+        ans
+    }
+
+    fn encode_into(&self, id: NodeId, ans: &mut String) {
+        let encode_children = |ans: &mut String| {
+            for &child in self.children(id) {
+                self.encode_into(child, ans);
+            }
+        };
+        match self.kind(id) {
+            NodeKind::Root => encode_children(ans),
+            NodeKind::Element(element) => {
+                ans.push_str(&element.start.encode_head());
+                if element.has_content {
+                    ans.push(';');
+                    encode_children(ans);
+                }
+                if !element.unterminated {
+                    ans.push('}');
+                }
+            }
+            NodeKind::Text(text) => ans.push_str(text.src()),
+            NodeKind::Comment(comment) => ans.push_str(&comment.encode_cptml()),
+            NodeKind::CodeBlock(code) => ans.push_str(&code.encode_cptml()),
+            NodeKind::Math(code) => ans.push_str(&code.encode_cptml()),
+            NodeKind::ViewStart(tag) => ans.push_str(&tag.encode_cptml()),
+            NodeKind::ViewEnd(tag) => ans.push_str(&tag.encode_cptml()),
+            NodeKind::Error(error) => ans.push_str(error.src()),
+        }
+    }
+
+    pub fn encode_cptml(&self) -> String {
+        self.encode_node(self.root())
+    }
+}
+
+pub struct Ancestors<'d, 'a> {
+    doc: &'d Document<'a>,
+    next: Option<NodeId>,
+}
+
+impl<'d, 'a> Iterator for Ancestors<'d, 'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.doc.parent(id);
+        Some(id)
+    }
+}
+
+pub struct Descendants<'d, 'a> {
+    doc: &'d Document<'a>,
+    // The next node is the last one
+    stack: Vec<NodeId>,
+}
+
+impl<'d, 'a> Iterator for Descendants<'d, 'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.stack.pop()?;
+        self.stack
+            .extend(self.doc.children(id).iter().rev().copied());
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{
+        parse_document, parse_document_recovering, parse_document_with, ParseOptions,
+    };
+    use crate::dom::*;
+    use crate::prelude::{CptmlError, CptmlResult};
+
+    fn name(doc: &Document, id: NodeId) -> String {
+        match doc.kind(id) {
+            NodeKind::Element(element) => element.name().encode_cptml(),
+            NodeKind::Text(text) => format!("{:?}", text.meaning()),
+            other => format!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dom_navigation() {
+        let src = "{a; {b x=1; one {c}} {- z -}``k``$m$ <(t)l|{d;two}|(t)l>}tail";
+        let doc = Document::new(parse_document(src).unwrap());
+        let root = doc.root();
+        assert_eq!(doc.parent(root), None);
+        assert_eq!(doc.children(root).len(), 2);
+        let a = doc.first_child(root).unwrap();
+        assert_eq!(name(&doc, a), "a");
+        assert_eq!(doc.parent(a), Some(root));
+        assert_eq!(name(&doc, doc.next_sibling(a).unwrap()), "\"tail\"");
+        assert_eq!(doc.previous_sibling(a), None);
+        assert_eq!(doc.next_sibling(doc.last_child(root).unwrap()), None);
+
+        let b = doc.child(a, 1).unwrap();
+        assert_eq!(name(&doc, b), "b");
+        assert_eq!(doc.index(b), Some(1));
+        assert_eq!(doc.span(b), Span::new2(4, 1, 4, 20, 1, 20));
+        match doc.kind(b) {
+            NodeKind::Element(element) => {
+                assert!(element.has_content());
+                assert!(element.attrs().contains("x"));
+            }
+            other => panic!("{:?}", other),
+        }
+        let c = doc.last_child(b).unwrap();
+        assert!(matches!(doc.kind(c), NodeKind::Element(e) if !e.has_content()));
+        assert_eq!(doc.ancestors(c).collect::<Vec<_>>(), [b, a, root]);
+        assert_eq!(doc.text(b), " one ");
+        assert_eq!(doc.text(a), "  one   two");
+
+        let kinds: Vec<String> = doc
+            .descendants(a)
+            .map(|id| match doc.kind(id) {
+                NodeKind::Element(_) | NodeKind::Text(_) => name(&doc, id),
+                NodeKind::Comment(_) => "comment".to_string(),
+                NodeKind::CodeBlock(_) => "code".to_string(),
+                NodeKind::Math(_) => "math".to_string(),
+                NodeKind::ViewStart(_) => "start".to_string(),
+                NodeKind::ViewEnd(_) => "end".to_string(),
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            kinds.join(" "),
+            "\" \" b \" one \" c \" \" comment code math \" \" start d \"two\" end"
+        );
+        assert_eq!(doc.descendants(c).count(), 0);
+        assert_eq!(doc.descendants(root).count(), 15);
+    }
+
+    #[test]
+    fn test_dom_encode_cptml() {
+        let src = "{a; {b x=1 ; one {c}} {- z -}``k``$m$ <(t)l|{d;two}|(t)l>}tail";
+        let doc = Document::new(parse_document(src).unwrap());
+        assert_eq!(doc.encode_cptml(), src);
+        let b = doc.child(doc.root(), 0).and_then(|a| doc.child(a, 1));
+        assert_eq!(doc.encode_node(b.unwrap()), "{b x=1 ; one {c}}");
+
+        let src = include_str!("../../example.cptml");
+        let (ast_doc, _) = parse_document_recovering(src, &ParseOptions::default());
+        let doc = Document::new(ast_doc).into_owned();
+        assert_eq!(doc.encode_cptml(), src);
+
+        // The "}" that is missing is not added
+        let src = "{p;a=\n";
+        let (ast_doc, _) = parse_document_recovering(src, &ParseOptions::default());
+        let doc = Document::new(ast_doc);
+        let p = doc.child(doc.root(), 0).unwrap();
+        assert!(matches!(doc.kind(p), NodeKind::Element(e) if e.is_unterminated()));
+        assert_eq!(doc.encode_cptml(), src);
+    }
+
+    #[test]
+    fn test_dom_mutation() {
+        let src = "{p  class=\"x\" ; Hello {- keep  -} world}\n{q}\n";
+        let mut doc = Document::new(parse_document(src).unwrap());
+        let root = doc.root();
+        let p = doc.child(root, 0).unwrap();
+        let q = doc.child(root, 2).unwrap();
+
+        doc.set_attr(p, "class", "\"y\"").unwrap();
+        doc.set_attr(p, "n", "1").unwrap();
+        assert_eq!(
+            doc.encode_node(p),
+            "{p  class=\"y\" n=1 ; Hello {- keep  -} world}"
+        );
+        assert_eq!(doc.remove_attr(p, "class"), Ok(true));
+        assert_eq!(doc.remove_attr(p, "class"), Ok(false));
+        assert_eq!(doc.encode_node(p), "{p n=1 ; Hello {- keep  -} world}");
+
+        // Wraps "Hello" and moves the comment into the new element
+        let hello = doc.split_text(doc.first_child(p).unwrap(), 1).unwrap();
+        doc.split_text(hello, 5).unwrap();
+        let b = doc.wrap(hello, hello, "{b}").unwrap();
+        assert_eq!(doc.text(b), "Hello");
+        let comment = doc.child(p, 3).unwrap();
+        doc.move_node(comment, b, 1).unwrap();
+        assert_eq!(doc.encode_node(p), "{p n=1 ; {b;Hello{- keep  -}}  world}");
+
+        let ids = doc.insert_cptml(q, 0, "new {i; text}").unwrap();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(doc.parent(ids[1]), Some(q));
+        assert_eq!(doc.span(ids[1]), Span::new());
+        doc.remove(comment).unwrap();
+        assert_eq!(doc.parent(comment), None);
+        doc.move_node(ids[1], root, 3).unwrap();
+        assert_eq!(
+            doc.encode_cptml(),
+            "{p n=1 ; {b;Hello}  world}\n{q;new }{i; text}\n"
+        );
+        // Moving within the same parent
+        doc.move_node(ids[1], root, 0).unwrap();
+        assert_eq!(
+            doc.encode_cptml(),
+            "{i; text}{p n=1 ; {b;Hello}  world}\n{q;new }\n"
+        );
+        // Untouched nodes keep their spans
+        assert_eq!(doc.span(q), Span::new2(41, 2, 0, 44, 2, 3));
+    }
+
+    #[test]
+    fn test_dom_mutation_version_1() {
+        let src = "{.cptml version=1}{a; x}";
+        let mut doc = Document::new(parse_document(src).unwrap());
+        assert_eq!(doc.dialect(), Dialect::V1);
+        let a = doc.child(doc.root(), 1).unwrap();
+        let b = doc.append_cptml(a, "{b; /* c */}").unwrap()[0];
+        let comment = doc.child(b, 1).unwrap();
+        assert!(matches!(doc.kind(comment), NodeKind::Comment(_)));
+        doc.set_attr(b, ".id", "\"y\"").unwrap();
+        doc.set_attr(b, "!id", "\"z\"").unwrap();
+        assert_eq!(doc.encode_node(b), "{b !id=\"z\"; /* c */}");
+        assert!(doc.append_cptml(a, "{!c; {- d -}}").is_err());
+        let new_src = doc.encode_cptml();
+        assert_eq!(parse_document(&new_src).unwrap().encode_cptml(), new_src);
+
+        // The elements around an inserted piece count towards the nesting limit
+        let options = ParseOptions {
+            max_depth: 4,
+            ..ParseOptions::default()
+        };
+        let mut doc =
+            Document::new_with(parse_document_with("{a;{b}}", &options).unwrap(), &options);
+        let a = doc.child(doc.root(), 0).unwrap();
+        let b = doc.child(a, 0).unwrap();
+        assert!(doc.append_cptml(a, "{c;{d;{e}}}").is_ok());
+        assert!(doc.append_cptml(b, "{c;{d;{e}}}").is_err());
+        assert!(doc.set_attr(b, "x", "<>{c}</>").is_ok());
+        assert!(doc.set_attr(b, "x", "<>{c;{d}}</>").is_err());
+    }
+
+    #[test]
+    fn test_dom_mutation_errors() {
+        let src = "{p; a\\n {b; c}}";
+        let mut doc = Document::new(parse_document(src).unwrap());
+        let root = doc.root();
+        let p = doc.child(root, 0).unwrap();
+        let text = doc.child(p, 0).unwrap();
+        let b = doc.child(p, 1).unwrap();
+        let invalid =
+            |result: CptmlResult<()>| matches!(result, Err(CptmlError::InvalidArgument(_)));
+
+        assert!(invalid(doc.set_attr(text, "x", "1")));
+        assert!(invalid(doc.set_attr(p, "x", "1 y=2")));
+        assert!(matches!(
+            doc.set_attr(p, "x", "\"open"),
+            Err(CptmlError::Parse(_))
+        ));
+        assert!(invalid(doc.insert_cptml(p, 3, "x").map(|_| ())));
+        assert!(invalid(doc.insert_cptml(text, 0, "x").map(|_| ())));
+        assert!(invalid(doc.move_node(p, b, 0)));
+        assert!(invalid(doc.move_node(b, p, 2)));
+        assert!(invalid(doc.remove(root)));
+        assert!(invalid(doc.wrap(b, text, "{i}").map(|_| ())));
+        assert!(invalid(doc.wrap(text, b, "{i; x}").map(|_| ())));
+        // Inside of "\\n"
+        assert!(doc.split_text(text, 3).is_err());
+        assert!(invalid(doc.split_text(text, 0).map(|_| ())));
+        assert_eq!(doc.encode_cptml(), src);
+    }
+}
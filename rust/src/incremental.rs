@@ -0,0 +1,300 @@
+// Parses a document again after some edits. Only the innermost curly tag or comment around the
+// edits is parsed and every other node is taken from the old tree, with its spans moved to where
+// the node is now. If that doesn't work (e.g. an edit added a "}" that closes the tag earlier),
+// the next enclosing tag is tried and, in the end, the whole document.
+
+use std::ops::Range;
+
+use crate::ast::{
+    check_document, node, parse_document_with, with_context_at, Document, Node, ParseOptions,
+    Rebase,
+};
+use crate::dialect::{detect_dialect, Dialect};
+use crate::pos::{Position, Span};
+use crate::prelude::{CptmlError, CptmlResult};
+
+// Replaces the bytes in range, which refers to the source before any of the edits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+impl TextEdit {
+    pub fn new(range: Range<usize>, replacement: &str) -> TextEdit {
+        TextEdit {
+            range: range,
+            replacement: replacement.to_string(),
+        }
+    }
+}
+
+// The part of the old source that the edits change
+fn changed_range(edits: &[TextEdit], old_len: usize) -> CptmlResult<Option<Range<usize>>> {
+    for edit in edits {
+        if edit.range.start > edit.range.end || edit.range.end > old_len {
+            let msg = format!("edit {:?} is outside of the source", edit.range);
+            return Err(CptmlError::InvalidArgument(msg));
+        }
+    }
+    for pair in edits.windows(2) {
+        if pair[0].range.end > pair[1].range.start {
+            let msg = format!(
+                "edits {:?} and {:?} are out of order",
+                pair[0].range, pair[1].range
+            );
+            return Err(CptmlError::InvalidArgument(msg));
+        }
+    }
+    Ok(match (edits.first(), edits.last()) {
+        (Some(first), Some(last)) => Some(first.range.start..last.range.end),
+        _ => None,
+    })
+}
+
+// Curly tags and comments are the only nodes that can't be affected by the ones around them
+fn encloses(node: &Node, changed: &Range<usize>) -> bool {
+    let span = node.span();
+    matches!(node, Node::CurlyTag(_) | Node::Comment(_))
+        && span.start.byte < changed.start
+        && changed.end < span.end.byte
+}
+
+// The indexes of the nodes around the changed range, from the outermost to the innermost
+fn enclosing_path(mut nodes: &[Node], changed: &Range<usize>) -> Vec<usize> {
+    let mut path = Vec::new();
+    while let Some(idx) = nodes.iter().position(|node| encloses(node, changed)) {
+        path.push(idx);
+        nodes = match &nodes[idx] {
+            Node::CurlyTag(tag) => tag.content(),
+            _ => break,
+        };
+    }
+    path
+}
+
+fn node_at_mut<'d, 'a>(nodes: &'d mut Vec<Node<'a>>, path: &[usize]) -> Option<&'d mut Node<'a>> {
+    let (last, parents) = path.split_last()?;
+    let mut nodes = nodes;
+    for &idx in parents {
+        nodes = match &mut nodes[idx] {
+            Node::CurlyTag(tag) => tag.content_mut()?,
+            _ => return None,
+        };
+    }
+    nodes.get_mut(*last)
+}
+
+// Moves whatever comes after old_end so that it comes after new_end
+fn shift(span: Span, old_end: Position, new_end: Position) -> Span {
+    let shift_pos = |pos: Position| match pos.byte >= old_end.byte {
+        true => pos.relative_to(old_end).rebase(new_end),
+        false => pos,
+    };
+    Span::new_from_to(shift_pos(span.start), shift_pos(span.end))
+}
+
+// Parses one of the nodes around the changed range again and puts it in a copy of the old tree
+fn reparse_nodes<'a>(
+    old: &Document<'a>,
+    new_src: &'a str,
+    changed: &Range<usize>,
+    len_diff: isize,
+    options: &ParseOptions,
+) -> Option<Document<'a>> {
+    let path = enclosing_path(old.nodes(), changed);
+    let mut doc = old.clone();
+    for depth in (1..path.len() + 1).rev() {
+        let old_span = node_at_mut(doc.nodes_mut(), &path[..depth])?.span();
+        let new_end_byte = (old_span.end.byte as isize + len_diff) as usize;
+        let text = match new_src.get(old_span.start.byte..new_end_byte) {
+            Some(text) => text,
+            None => continue,
+        };
+        // The tags around the node count towards the nesting limit
+        let parsed = with_context_at(Dialect::LATEST, options, depth - 1, || node(text));
+        let mut new_node = match parsed {
+            Ok(("", new_node)) => new_node,
+            _ => continue,
+        };
+        new_node.rebase(old_span.start);
+        let new_end = old_span.start.after(text);
+        doc.nodes_mut()
+            .map_spans(&|span| shift(span, old_span.end, new_end));
+        *node_at_mut(doc.nodes_mut(), &path[..depth])? = new_node;
+        return Some(doc);
+    }
+    None
+}
+
+// Gives the same result as parsing new_src, which must be the source of old after the edits.
+// The edits must be in order and may not overlap.
+pub fn reparse_document<'a>(
+    old: &Document<'a>,
+    new_src: &'a str,
+    edits: &[TextEdit],
+) -> CptmlResult<Document<'a>> {
+    reparse_document_with(old, new_src, edits, &ParseOptions::default())
+}
+
+pub fn reparse_document_with<'a>(
+    old: &Document<'a>,
+    new_src: &'a str,
+    edits: &[TextEdit],
+    options: &ParseOptions,
+) -> CptmlResult<Document<'a>> {
+    let old_len = old.nodes().last().map_or(0, |node| node.span().end.byte);
+    let changed = match changed_range(edits, old_len)? {
+        Some(changed) => changed,
+        None => return parse_document_with(new_src, options),
+    };
+    // Only documents in the latest dialect are parsed again in parts
+    if detect_dialect(new_src)? != Dialect::LATEST {
+        return parse_document_with(new_src, options);
+    }
+    let len_diff: isize = edits
+        .iter()
+        .map(|edit| edit.replacement.len() as isize - edit.range.len() as isize)
+        .sum();
+    if old_len as isize + len_diff != new_src.len() as isize {
+        let msg = "the edits do not match the new source".to_string();
+        return Err(CptmlError::InvalidArgument(msg));
+    }
+    let mut doc = match reparse_nodes(old, new_src, &changed, len_diff, options) {
+        Some(doc) => doc,
+        None => return parse_document_with(new_src, options),
+    };
+    match check_document(&mut doc, options).into_iter().next() {
+        Some(err) => Err(err.into()),
+        None => Ok(doc),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{parse_document, Node};
+    use crate::incremental::*;
+
+    // Applies the edits in reverse so that the ranges stay valid
+    fn apply(src: &str, edits: &[TextEdit]) -> String {
+        let mut ans = src.to_string();
+        for edit in edits.iter().rev() {
+            ans.replace_range(edit.range.clone(), &edit.replacement);
+        }
+        ans
+    }
+
+    fn text_src<'a>(node: &'a Node) -> &'a str {
+        match node {
+            Node::Text(text) => text.src(),
+            Node::CurlyTag(tag) => text_src(&tag.content()[0]),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn is_in(text: &str, src: &str) -> bool {
+        let range = src.as_bytes().as_ptr_range();
+        range.contains(&text.as_ptr())
+    }
+
+    #[test]
+    fn test_reparse_document() {
+        let src = "{a; {b; x}\n  {c; y}} tail\n{d;\n  z}";
+        let old = parse_document(src).unwrap();
+        for edits in [
+            vec![TextEdit::new(8..9, "xyz\n\n")],
+            vec![TextEdit::new(8..9, ""), TextEdit::new(18..18, "é")],
+            vec![TextEdit::new(5..6, "i x=1")],
+            vec![TextEdit::new(19..19, "\n")],
+            vec![TextEdit::new(33..33, "{- w -}")],
+            // The edit changes where the tags end
+            vec![TextEdit::new(9..9, "}")],
+            vec![TextEdit::new(9..10, "")],
+            vec![TextEdit::new(20..26, "")],
+            vec![TextEdit::new(0..0, "{e}")],
+            vec![],
+        ] {
+            let new_src = apply(src, &edits);
+            assert_eq!(
+                reparse_document(&old, &new_src, &edits),
+                parse_document(&new_src),
+                "{:?}",
+                edits
+            );
+        }
+
+        // Only {b; ...} is parsed again
+        let edits = [TextEdit::new(8..9, "w")];
+        let new_src = apply(src, &edits);
+        let doc = reparse_document(&old, &new_src, &edits).unwrap();
+        let a = match &doc.nodes()[0] {
+            Node::CurlyTag(tag) => tag,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(text_src(&a.content()[1]), " w");
+        assert!(is_in(text_src(&a.content()[1]), &new_src));
+        assert!(is_in(text_src(&a.content()[0]), src));
+        assert!(is_in(text_src(&a.content()[3]), src));
+        assert!(is_in(text_src(&doc.nodes()[1]), src));
+        assert!(is_in(text_src(&doc.nodes()[2]), src));
+    }
+
+    #[test]
+    fn test_reparse_document_errors() {
+        let src = "{a; <(t)x|{b; y}|(t)x>}";
+        let old = parse_document(src).unwrap();
+        let edits = [TextEdit::new(14..15, "\\q")];
+        let new_src = apply(src, &edits);
+        assert_eq!(
+            reparse_document(&old, &new_src, &edits),
+            parse_document(&new_src)
+        );
+        // The views are checked again even if only {b; ...} changed
+        let edits = [TextEdit::new(14..15, "|(t)x>")];
+        let new_src = apply(src, &edits);
+        assert!(reparse_document(&old, &new_src, &edits).is_err());
+
+        let edits = [TextEdit::new(3..4, ""), TextEdit::new(1..2, "")];
+        assert!(matches!(
+            reparse_document(&old, "a; <(t)x|{b; y}|(t)x>}", &edits),
+            Err(CptmlError::InvalidArgument(_))
+        ));
+        let edits = [TextEdit::new(20..30, "")];
+        assert!(matches!(
+            reparse_document(&old, "", &edits),
+            Err(CptmlError::InvalidArgument(_))
+        ));
+        let edits = [TextEdit::new(1..2, "")];
+        assert!(matches!(
+            reparse_document(&old, src, &edits),
+            Err(CptmlError::InvalidArgument(_))
+        ));
+
+        // The tags around the one parsed again count towards the nesting limit
+        let options = ParseOptions {
+            max_depth: 4,
+            ..ParseOptions::default()
+        };
+        let src = "{a;{a;{a; x}}}";
+        let old = parse_document_with(src, &options).unwrap();
+        let edits = [TextEdit::new(10..11, "{b;{c; y}}")];
+        let new_src = apply(src, &edits);
+        assert!(parse_document_with(&new_src, &options).is_err());
+        assert_eq!(
+            reparse_document_with(&old, &new_src, &edits, &options),
+            parse_document_with(&new_src, &options)
+        );
+        let edits = [TextEdit::new(10..11, "{b; y}")];
+        let new_src = apply(src, &edits);
+        assert!(reparse_document_with(&old, &new_src, &edits, &options).is_ok());
+
+        let src = "{.cptml version=1}{a; {b; /* x */}}";
+        let old = parse_document(src).unwrap();
+        let edits = [TextEdit::new(29..30, "y")];
+        let new_src = apply(src, &edits);
+        assert_eq!(
+            reparse_document(&old, &new_src, &edits),
+            parse_document(&new_src)
+        );
+    }
+}
@@ -1,2 +1,20 @@
 pub mod ast;
+pub mod attrs;
+pub mod decimal;
+pub mod dialect;
+mod diagnostics;
+pub mod dom;
+pub mod incremental;
+pub mod number;
+pub mod pos;
 pub mod prelude;
+pub mod reader;
+pub mod semantic;
+pub mod views;
+
+pub use ast::{parse_document, parse_document_recovering, parse_document_with, ParseOptions};
+pub use dialect::{detect_dialect, migrate, Dialect};
+pub use incremental::{reparse_document, reparse_document_with, TextEdit};
+pub use reader::{EventParser, EventReader};
+pub use semantic::resolve_document;
+pub use views::resolve_views;
@@ -0,0 +1,423 @@
+use nom::error::ErrorKind;
+use std::convert::TryFrom;
+
+use crate::decimal::Decimal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberError {
+    // E.g. "-", "0x", "." or "+,"
+    NoDigits,
+    // E.g. "0b102" or "0x_G"
+    InvalidDigit(char),
+    // Underscores may only appear between digits. E.g. "_1", "1_" or "1._5"
+    MisplacedUnderscore,
+    // E.g. "1e" or "1E+"
+    MissingExponent,
+    // The value does not fit in the requested type. E.g. "0xFFFF_FFFF_FFFF_FFFF_F" for an i64
+    Overflow,
+    // E.g. "1.5" or "1e3" where an integer was expected
+    NotAnInteger,
+}
+
+impl NumberError {
+    pub fn error_kind(&self) -> ErrorKind {
+        match self {
+            NumberError::NoDigits => ErrorKind::Digit,
+            NumberError::InvalidDigit(_) => ErrorKind::Digit,
+            NumberError::MisplacedUnderscore => ErrorKind::Digit,
+            NumberError::MissingExponent => ErrorKind::Digit,
+            NumberError::Overflow => ErrorKind::TooLarge,
+            NumberError::NotAnInteger => ErrorKind::Digit,
+        }
+    }
+}
+
+impl std::fmt::Display for NumberError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NumberError::NoDigits => write!(f, "number has no digits"),
+            NumberError::InvalidDigit(ch) => write!(f, "invalid digit {:?} in number", ch),
+            NumberError::MisplacedUnderscore => {
+                write!(f, "underscores are only allowed between digits")
+            }
+            NumberError::MissingExponent => write!(f, "exponent has no digits"),
+            NumberError::Overflow => write!(f, "number is too large"),
+            NumberError::NotAnInteger => write!(f, "number is not an integer"),
+        }
+    }
+}
+
+impl std::error::Error for NumberError {}
+
+// A numeric literal split into its parts. E.g. "-1_000,5e+3" becomes
+// { negative: true, radix: 10, int_digits: "1000", frac_digits: Some("5"), exponent: Some(3) }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumberLiteral {
+    negative: bool,
+    radix: u32,
+    int_digits: String,
+    frac_digits: Option<String>,
+    exponent: Option<i64>,
+}
+
+fn is_radix_prefix(input: &str, ch: char) -> bool {
+    let mut input_chars = input.chars();
+    input_chars.next() == Some('0')
+        && input_chars.next().map(|c| c.to_ascii_lowercase()) == Some(ch)
+}
+
+fn count_bytes_while(input: &str, pred: impl Fn(char) -> bool) -> usize {
+    input
+        .char_indices()
+        .find(|(_, ch)| !pred(*ch))
+        .map(|(pos, _)| pos)
+        .unwrap_or(input.len())
+}
+
+fn is_digit_or_underscore(ch: char) -> bool {
+    ch.is_ascii_digit() || ch == '_'
+}
+
+// Returns how many bytes at the start of the input look like a number. The result may still be
+// an invalid number (e.g. "-" or "0b12"), which is reported by NumberLiteral::parse.
+// If decimal_comma is true, a comma followed by a digit is taken as the decimal separator.
+pub fn number_lexeme_len(input: &str, decimal_comma: bool) -> usize {
+    let mut n_bytes = 0;
+    if input.starts_with('+') || input.starts_with('-') {
+        n_bytes += 1;
+    }
+    let rest = &input[n_bytes..];
+    if is_radix_prefix(rest, 'x') {
+        n_bytes += 2;
+        n_bytes += count_bytes_while(&input[n_bytes..], |ch| ch.is_ascii_hexdigit() || ch == '_');
+        return n_bytes;
+    }
+    if is_radix_prefix(rest, 'b') {
+        n_bytes += 2;
+        n_bytes += count_bytes_while(&input[n_bytes..], is_digit_or_underscore);
+        return n_bytes;
+    }
+
+    n_bytes += count_bytes_while(rest, is_digit_or_underscore);
+    let rest = &input[n_bytes..];
+    let after_sep = rest.chars().nth(1);
+    if rest.starts_with('.')
+        || (decimal_comma
+            && rest.starts_with(',')
+            && after_sep.is_some_and(|ch| ch.is_ascii_digit()))
+    {
+        n_bytes += 1;
+        n_bytes += count_bytes_while(&input[n_bytes..], is_digit_or_underscore);
+    }
+    if n_bytes == 0 {
+        return 0;
+    }
+
+    let rest = &input[n_bytes..];
+    if rest.starts_with('e') || rest.starts_with('E') {
+        n_bytes += 1;
+        if input[n_bytes..].starts_with('+') || input[n_bytes..].starts_with('-') {
+            n_bytes += 1;
+        }
+        n_bytes += count_bytes_while(&input[n_bytes..], is_digit_or_underscore);
+    }
+    return n_bytes;
+}
+
+// Removes the underscores from a group of digits and checks that all digits are valid
+fn clean_digits(digits: &str, radix: u32) -> Result<String, NumberError> {
+    if digits.starts_with('_') || digits.ends_with('_') {
+        return Err(NumberError::MisplacedUnderscore);
+    }
+    let mut ans = String::with_capacity(digits.len());
+    for ch in digits.chars() {
+        if ch == '_' {
+            continue;
+        }
+        if !ch.is_digit(radix) {
+            return Err(NumberError::InvalidDigit(ch));
+        }
+        ans.push(ch);
+    }
+    Ok(ans)
+}
+
+impl NumberLiteral {
+    // Parses a whole lexeme (as delimited by number_lexeme_len) without ever panicking
+    pub fn parse(lexeme: &str) -> Result<NumberLiteral, NumberError> {
+        let (negative, rest) = match lexeme.chars().next() {
+            Some('-') => (true, &lexeme[1..]),
+            Some('+') => (false, &lexeme[1..]),
+            _ => (false, lexeme),
+        };
+
+        let radix = if is_radix_prefix(rest, 'x') {
+            16
+        } else if is_radix_prefix(rest, 'b') {
+            2
+        } else {
+            10
+        };
+        if radix != 10 {
+            let int_digits = clean_digits(&rest[2..], radix)?;
+            if int_digits.is_empty() {
+                return Err(NumberError::NoDigits);
+            }
+            return Ok(NumberLiteral {
+                negative,
+                radix,
+                int_digits,
+                frac_digits: None,
+                exponent: None,
+            });
+        }
+
+        let (mantissa, exponent) = match rest.find(['e', 'E']) {
+            Some(pos) => (&rest[..pos], Some(&rest[pos + 1..])),
+            None => (rest, None),
+        };
+        let (int_part, frac_part) = match mantissa.find(['.', ',']) {
+            Some(pos) => (&mantissa[..pos], Some(&mantissa[pos + 1..])),
+            None => (mantissa, None),
+        };
+        let int_digits = clean_digits(int_part, 10)?;
+        let frac_digits = match frac_part {
+            Some(digits) => Some(clean_digits(digits, 10)?),
+            None => None,
+        };
+        if int_digits.is_empty() && frac_digits.as_deref().unwrap_or_default().is_empty() {
+            return Err(NumberError::NoDigits);
+        }
+        let exponent = match exponent {
+            Some(exp) => {
+                let (exp_negative, exp_digits) = match exp.chars().next() {
+                    Some('-') => (true, &exp[1..]),
+                    Some('+') => (false, &exp[1..]),
+                    _ => (false, exp),
+                };
+                let exp_digits = clean_digits(exp_digits, 10)?;
+                if exp_digits.is_empty() {
+                    return Err(NumberError::MissingExponent);
+                }
+                let exp: i64 = exp_digits.parse().map_err(|_| NumberError::Overflow)?;
+                Some(if exp_negative { -exp } else { exp })
+            }
+            None => None,
+        };
+
+        Ok(NumberLiteral {
+            negative,
+            radix,
+            int_digits,
+            frac_digits,
+            exponent,
+        })
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn radix(&self) -> u32 {
+        self.radix
+    }
+
+    // Digits before the decimal separator (or all digits for non decimal numbers)
+    pub fn int_digits(&self) -> &str {
+        &self.int_digits
+    }
+
+    // Digits after the decimal separator, if there is one
+    pub fn frac_digits(&self) -> Option<&str> {
+        self.frac_digits.as_deref()
+    }
+
+    pub fn exponent(&self) -> Option<i64> {
+        self.exponent
+    }
+
+    // True if the literal has neither a decimal separator nor an exponent
+    pub fn is_integer(&self) -> bool {
+        self.frac_digits.is_none() && self.exponent.is_none()
+    }
+
+    pub fn to_i64(&self) -> Result<i64, NumberError> {
+        if !self.is_integer() {
+            return Err(NumberError::NotAnInteger);
+        }
+        let mut magnitude: u64 = 0;
+        for ch in self.int_digits.chars() {
+            let digit = ch
+                .to_digit(self.radix)
+                .ok_or(NumberError::InvalidDigit(ch))?;
+            magnitude = magnitude
+                .checked_mul(self.radix as u64)
+                .and_then(|val| val.checked_add(digit as u64))
+                .ok_or(NumberError::Overflow)?;
+        }
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+                .ok_or(NumberError::Overflow)
+        } else {
+            i64::try_from(magnitude).map_err(|_| NumberError::Overflow)
+        }
+    }
+
+    // Exact value of the literal, even for things like "0.1" or "1e-30" which f64 can't hold
+    pub fn to_decimal(&self) -> Result<Decimal, NumberError> {
+        if self.radix != 10 {
+            return self.to_i64().map(Decimal::from);
+        }
+        let frac_digits = self.frac_digits.as_deref().unwrap_or_default();
+        let digits = format!("{}{}", self.int_digits, frac_digits);
+        let scale = (frac_digits.len() as i64)
+            .checked_sub(self.exponent.unwrap_or(0))
+            .ok_or(NumberError::Overflow)?;
+        Decimal::new(self.negative, &digits, scale)
+    }
+}
+
+// Parses a whole string as a number
+pub fn parse_number(input: &str) -> Result<NumberLiteral, NumberError> {
+    let n_bytes = number_lexeme_len(input, true);
+    if n_bytes != input.len() {
+        return match input[n_bytes..].chars().next() {
+            Some(ch) if n_bytes > 0 => Err(NumberError::InvalidDigit(ch)),
+            _ => Err(NumberError::NoDigits),
+        };
+    }
+    NumberLiteral::parse(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::number::*;
+
+    #[test]
+    fn test_number_lexeme_len() {
+        assert_eq!(number_lexeme_len("", true), 0);
+        assert_eq!(number_lexeme_len("abc", true), 0);
+        assert_eq!(number_lexeme_len("-", true), 1);
+        assert_eq!(number_lexeme_len("_", true), 1);
+        assert_eq!(number_lexeme_len("12 ", true), 2);
+        assert_eq!(number_lexeme_len("+3.2e3;", true), 6);
+        assert_eq!(number_lexeme_len("0xFF_ff}", true), 7);
+        assert_eq!(number_lexeme_len("0b1012", true), 6);
+        assert_eq!(number_lexeme_len("1,5", true), 3);
+        assert_eq!(number_lexeme_len("1,5", false), 1);
+        assert_eq!(number_lexeme_len("1, 5", true), 1);
+        assert_eq!(number_lexeme_len("1e", true), 2);
+        assert_eq!(number_lexeme_len(".5", true), 2);
+    }
+
+    #[test]
+    fn test_number_literal_parse() {
+        assert_eq!(
+            NumberLiteral::parse("-1_000,5e+3"),
+            Ok(NumberLiteral {
+                negative: true,
+                radix: 10,
+                int_digits: "1000".to_string(),
+                frac_digits: Some("5".to_string()),
+                exponent: Some(3),
+            })
+        );
+        assert_eq!(
+            NumberLiteral::parse("0b1010"),
+            Ok(NumberLiteral {
+                negative: false,
+                radix: 2,
+                int_digits: "1010".to_string(),
+                frac_digits: None,
+                exponent: None,
+            })
+        );
+        assert_eq!(NumberLiteral::parse(""), Err(NumberError::NoDigits));
+        assert_eq!(NumberLiteral::parse("-"), Err(NumberError::NoDigits));
+        assert_eq!(NumberLiteral::parse("+"), Err(NumberError::NoDigits));
+        assert_eq!(NumberLiteral::parse("."), Err(NumberError::NoDigits));
+        assert_eq!(NumberLiteral::parse("0x"), Err(NumberError::NoDigits));
+        assert_eq!(
+            NumberLiteral::parse("_"),
+            Err(NumberError::MisplacedUnderscore)
+        );
+        assert_eq!(
+            NumberLiteral::parse("1_"),
+            Err(NumberError::MisplacedUnderscore)
+        );
+        assert_eq!(
+            NumberLiteral::parse("1._5"),
+            Err(NumberError::MisplacedUnderscore)
+        );
+        assert_eq!(
+            NumberLiteral::parse("0x_1"),
+            Err(NumberError::MisplacedUnderscore)
+        );
+        assert_eq!(
+            NumberLiteral::parse("0b102"),
+            Err(NumberError::InvalidDigit('2'))
+        );
+        assert_eq!(
+            NumberLiteral::parse("1e"),
+            Err(NumberError::MissingExponent)
+        );
+        assert_eq!(
+            NumberLiteral::parse("1e-"),
+            Err(NumberError::MissingExponent)
+        );
+        assert_eq!(
+            NumberLiteral::parse("1e99999999999999999999"),
+            Err(NumberError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_number_literal_to_i64() {
+        let to_i64 = |s: &str| NumberLiteral::parse(s).and_then(|n| n.to_i64());
+        assert_eq!(to_i64("0"), Ok(0));
+        assert_eq!(to_i64("+42"), Ok(42));
+        assert_eq!(to_i64("-34_343432"), Ok(-34343432));
+        assert_eq!(to_i64("0xA"), Ok(10));
+        assert_eq!(to_i64("-0xa"), Ok(-10));
+        assert_eq!(to_i64("0b1010"), Ok(10));
+        assert_eq!(to_i64("9223372036854775807"), Ok(i64::MAX));
+        assert_eq!(to_i64("-9223372036854775808"), Ok(i64::MIN));
+        assert_eq!(to_i64("9223372036854775808"), Err(NumberError::Overflow));
+        assert_eq!(
+            to_i64("0xFFFF_FFFF_FFFF_FFFF_F"),
+            Err(NumberError::Overflow)
+        );
+        assert_eq!(to_i64("1.0"), Err(NumberError::NotAnInteger));
+        assert_eq!(to_i64("1e3"), Err(NumberError::NotAnInteger));
+    }
+
+    #[test]
+    fn test_number_literal_to_decimal() {
+        let to_string = |s: &str| {
+            NumberLiteral::parse(s)
+                .and_then(|n| n.to_decimal())
+                .map(|n| n.to_string())
+        };
+        assert_eq!(to_string("0.0"), Ok("0.0".to_string()));
+        assert_eq!(to_string("-1.10"), Ok("-1.10".to_string()));
+        assert_eq!(to_string(".5"), Ok("0.5".to_string()));
+        assert_eq!(to_string("5."), Ok("5".to_string()));
+        assert_eq!(to_string("5.e1"), Ok("5e1".to_string()));
+        assert_eq!(to_string("1,25"), Ok("1.25".to_string()));
+        assert_eq!(to_string("125E-2"), Ok("1.25".to_string()));
+        assert_eq!(to_string("0x10"), Ok("16".to_string()));
+        assert_eq!(
+            to_string("0.1e-30"),
+            Ok("0.0000000000000000000000000000001".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("12").unwrap().to_i64(), Ok(12));
+        assert_eq!(parse_number(""), Err(NumberError::NoDigits));
+        assert_eq!(parse_number("abc"), Err(NumberError::NoDigits));
+        assert_eq!(parse_number("12abc"), Err(NumberError::InvalidDigit('a')));
+        assert_eq!(parse_number("1.2.3"), Err(NumberError::InvalidDigit('.')));
+    }
+}
@@ -0,0 +1,210 @@
+// Lines start at 1 and columns (counted in chars) at 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub byte: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new() -> Self {
+        Span {
+            start: Position::new(),
+            end: Position::new(),
+        }
+    }
+    pub fn new2(
+        start_byte: usize,
+        start_line: usize,
+        start_col: usize,
+        end_byte: usize,
+        end_line: usize,
+        end_col: usize,
+    ) -> Self {
+        Span {
+            start: Position::new2(start_byte, start_line, start_col),
+            end: Position::new2(end_byte, end_line, end_col),
+        }
+    }
+
+    pub fn new_from(pos: Position) -> Self {
+        Span {
+            start: pos,
+            end: pos,
+        }
+    }
+
+    pub fn new_from_to(start: Position, end: Position) -> Self {
+        Span {
+            start: start,
+            end: end,
+        }
+    }
+
+    // The span of text when it starts at the beginning of the input
+    pub fn of(text: &str) -> Self {
+        Span::new_from_to(Position::new(), Position::new().after(text))
+    }
+
+    pub fn step(&mut self, c: char) {
+        self.end.step(c)
+    }
+
+    pub fn rotate(&mut self) {
+        self.start = self.end;
+    }
+
+    // in bytes
+    pub fn len(&self) -> usize {
+        self.end.byte - self.start.byte
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // See Position::rebase
+    pub fn rebase(&self, base: Position) -> Self {
+        Span {
+            start: self.start.rebase(base),
+            end: self.end.rebase(base),
+        }
+    }
+
+    pub fn contains(&self, byte: usize) -> bool {
+        self.start.byte <= byte && byte < self.end.byte
+    }
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position::new()
+    }
+}
+
+impl Position {
+    pub fn new() -> Self {
+        Position {
+            byte: 0,
+            line: 1,
+            col: 0,
+        }
+    }
+    pub fn new2(byte: usize, line: usize, col: usize) -> Self {
+        Position {
+            byte: byte,
+            line: line,
+            col: col,
+        }
+    }
+
+    pub fn step(&mut self, c: char) {
+        self.byte += c.len_utf8();
+        self.col += 1;
+        if c == '\n' || c == '\u{0085}' || c == '\u{2028}' || c == '\u{2029}' {
+            self.line += 1;
+            self.col = 0;
+        }
+    }
+
+    // The position right after some text that starts here
+    pub fn after(&self, text: &str) -> Self {
+        let mut ans = *self;
+        for c in text.chars() {
+            ans.step(c);
+        }
+        ans
+    }
+
+    pub fn start_span(&self) -> Span {
+        Span {
+            start: *self,
+            end: *self,
+        }
+    }
+
+    // Turns a position relative to some text into a position relative to whatever comes before
+    // that text, given where the text starts (base).
+    pub fn rebase(&self, base: Position) -> Self {
+        Position {
+            byte: base.byte + self.byte,
+            line: base.line + self.line - 1,
+            col: if self.line == 1 {
+                base.col + self.col
+            } else {
+                self.col
+            },
+        }
+    }
+
+    // The inverse of rebase: the position relative to some text that starts at base, which must
+    // not come after this position
+    pub fn relative_to(&self, base: Position) -> Self {
+        Position {
+            byte: self.byte - base.byte,
+            line: self.line - base.line + 1,
+            col: if self.line == base.line {
+                self.col - base.col
+            } else {
+                self.col
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pos::*;
+
+    #[test]
+    fn test_position_after() {
+        assert_eq!(Position::new().after(""), Position::new2(0, 1, 0));
+        assert_eq!(Position::new().after("ab"), Position::new2(2, 1, 2));
+        assert_eq!(Position::new().after("a\nb"), Position::new2(3, 2, 1));
+        assert_eq!(Position::new().after("ç\u{2028}é"), Position::new2(7, 2, 1));
+        assert_eq!(
+            Position::new().after("a\u{0085}\u{2029}"),
+            Position::new2(6, 3, 0)
+        );
+        assert_eq!(Span::of("a\nbc"), Span::new2(0, 1, 0, 4, 2, 2));
+    }
+
+    #[test]
+    fn test_position_rebase() {
+        let base = Position::new2(10, 3, 4);
+        assert_eq!(Position::new().rebase(base), base);
+        assert_eq!(
+            Position::new2(2, 1, 2).rebase(base),
+            Position::new2(12, 3, 6)
+        );
+        assert_eq!(
+            Position::new2(5, 2, 1).rebase(base),
+            Position::new2(15, 4, 1)
+        );
+        assert_eq!(
+            Span::new2(1, 1, 1, 5, 2, 1).rebase(base),
+            Span::new2(11, 3, 5, 15, 4, 1)
+        );
+        assert_eq!(
+            Position::new2(15, 4, 1).relative_to(base),
+            Position::new2(5, 2, 1)
+        );
+        assert_eq!(
+            Position::new2(12, 3, 6).relative_to(base),
+            Position::new2(2, 1, 2)
+        );
+        // Rebasing in two steps is the same as rebasing once
+        let inner = Position::new2(3, 1, 3);
+        let middle = Position::new2(4, 2, 0);
+        assert_eq!(
+            inner.rebase(middle).rebase(base),
+            inner.rebase(middle.rebase(base))
+        );
+    }
+}
@@ -1,7 +1,284 @@
+use crate::attrs::DuplicateAttr;
+use crate::dialect::Dialect;
+use crate::number::NumberError;
+use crate::pos::{Position, Span};
+
 pub type CptmlResult<T> = Result<T, CptmlError>;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CptmlError {
     FauxPanic(String),
+    // The caller passed something the function can't work with, e.g. an index out of range
+    InvalidArgument(String),
     NotImplemented,
+    Parse(ParseError),
+    // From the reader of a stream (see reader.rs)
+    Io(std::io::ErrorKind, String),
+}
+
+impl CptmlError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CptmlError::Parse(err) => Some(err.span),
+            _ => None,
+        }
+    }
+
+    // See ParseError::render
+    pub fn render(&self, src: &str) -> String {
+        match self {
+            CptmlError::Parse(err) => err.render(src),
+            _ => format!("error: {}\n", self),
+        }
+    }
+}
+
+impl std::fmt::Display for CptmlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CptmlError::FauxPanic(msg) => write!(f, "{}", msg),
+            CptmlError::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            CptmlError::NotImplemented => write!(f, "not implemented"),
+            CptmlError::Parse(err) => write!(f, "{}", err),
+            CptmlError::Io(_, msg) => write!(f, "I/O error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CptmlError {}
+
+impl From<ParseError> for CptmlError {
+    fn from(err: ParseError) -> CptmlError {
+        CptmlError::Parse(err)
+    }
+}
+
+impl From<std::io::Error> for CptmlError {
+    fn from(err: std::io::Error) -> CptmlError {
+        CptmlError::Io(err.kind(), err.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    // E.g. "{- no end"
+    UnterminatedComment,
+    // E.g. "{b; no end"
+    UnterminatedCurlyTag,
+    // E.g. "<line n=1" or "|(t)line"
+    UnterminatedPointyTag,
+    // E.g. "``code`"
+    UnterminatedCodeBlock,
+    // E.g. "$$x^2$"
+    UnterminatedTexCode,
+    // E.g. "{a x=\"no end}"
+    UnterminatedString,
+    // E.g. "\q" or "\u{D800}"
+    BadEscape(String),
+    // Syntax of another dialect, e.g. the "\uE9;" escape of version 1 in a version 2 document
+    OnlyInDialect(String, Dialect),
+    InvalidNumber(NumberError),
+    // E.g. "<http://exa mple.com>"
+    InvalidUrl(String),
+    // Where an element, attribute or view name should be. E.g. "{1b}" or "<(1)line|"
+    InvalidName,
+    // A "}" without a curly tag to close
+    UnmatchedCurlyClose,
+    DuplicateAttr(DuplicateAttr),
+    // E.g. "|(t)line>" without a "<(t)line|" before it
+    UnknownViewClose {
+        view: String,
+        element: String,
+    },
+    // E.g. "<(t)a|<(t)b||(t)a>" (the open element is "b")
+    MismatchedViewClose {
+        view: String,
+        element: String,
+        open: String,
+    },
+    // E.g. "<(t)line|" without a "|(t)line>" after it
+    UnclosedViewElement {
+        view: String,
+        element: String,
+    },
+    UnexpectedChar(char),
+    UnexpectedEnd,
+    // Only for streams, since a &str is always valid
+    InvalidUtf8,
+    // E.g. "{!cptml version=9}" (see dialect.rs)
+    UnknownVersion(String),
+    // Something the target dialect can't express, e.g. "-}" in a comment that becomes "{- -}"
+    NotMigratable(String),
+    // Tags or attribute values nested deeper than the limit (see ParseOptions::max_depth)
+    TooDeeplyNested(usize),
+}
+
+impl std::fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseErrorKind::UnterminatedComment => write!(f, "unterminated comment"),
+            ParseErrorKind::UnterminatedCurlyTag => write!(f, "unterminated curly tag"),
+            ParseErrorKind::UnterminatedPointyTag => write!(f, "unterminated pointy tag"),
+            ParseErrorKind::UnterminatedCodeBlock => write!(f, "unterminated code block"),
+            ParseErrorKind::UnterminatedTexCode => write!(f, "unterminated TeX code"),
+            ParseErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            ParseErrorKind::BadEscape(escape) => write!(f, "invalid escape sequence {:?}", escape),
+            ParseErrorKind::OnlyInDialect(syntax, dialect) => write!(
+                f,
+                "{:?} is only valid in CPTML version {}",
+                syntax,
+                dialect.version()
+            ),
+            ParseErrorKind::InvalidNumber(err) => write!(f, "invalid number: {}", err),
+            ParseErrorKind::InvalidUrl(url) => write!(f, "invalid URL {:?}", url),
+            ParseErrorKind::InvalidName => write!(f, "invalid or missing name"),
+            ParseErrorKind::UnmatchedCurlyClose => write!(f, "\"}}\" does not close any tag"),
+            ParseErrorKind::DuplicateAttr(dup) => write!(f, "{}", dup),
+            ParseErrorKind::UnknownViewClose { view, element } => {
+                write!(f, "{:?} is not open in view {:?}", element, view)
+            }
+            ParseErrorKind::MismatchedViewClose {
+                view,
+                element,
+                open,
+            } => write!(
+                f,
+                "{:?} is closed while {:?} is still open in view {:?}",
+                element, open, view
+            ),
+            ParseErrorKind::UnclosedViewElement { view, element } => {
+                write!(f, "{:?} is never closed in view {:?}", element, view)
+            }
+            ParseErrorKind::UnexpectedChar(ch) => write!(f, "unexpected {:?}", ch),
+            ParseErrorKind::UnexpectedEnd => write!(f, "unexpected end of input"),
+            ParseErrorKind::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            ParseErrorKind::UnknownVersion(version) => {
+                write!(f, "unknown CPTML version {}", version)
+            }
+            ParseErrorKind::NotMigratable(reason) => write!(f, "cannot migrate: {}", reason),
+            ParseErrorKind::TooDeeplyNested(max_depth) => {
+                write!(f, "nested more than {} levels deep", max_depth)
+            }
+        }
+    }
 }
+
+// A problem with the input. The span points to the offending part of the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    // Boxed so that results with ParseErrors stay small
+    kind: Box<ParseErrorKind>,
+    span: Span,
+    // What could have made the input valid at the span, e.g. ["}", ";"] or ["attribute"]
+    expected: Vec<String>,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, span: Span) -> ParseError {
+        ParseError {
+            kind: Box::new(kind),
+            span: span,
+            expected: Vec::new(),
+        }
+    }
+
+    pub fn expecting(mut self, expected: &[&str]) -> ParseError {
+        self.expected = expected.iter().map(|item| item.to_string()).collect();
+        self
+    }
+
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn expected(&self) -> &[String] {
+        &self.expected
+    }
+
+    // See Position::rebase
+    pub fn rebase(mut self, base: Position) -> ParseError {
+        self.span = self.span.rebase(base);
+        self
+    }
+
+    // Shows the error with the line of the source where it happened. E.g.
+    //
+    // error: unterminated comment
+    //  --> 2:5
+    //   |
+    // 2 | abc {- def
+    //   |     ^^
+    //   = expected `-}`
+    pub fn render(&self, src: &str) -> String {
+        let line_no = self.span.start.line.to_string();
+        let gutter = " ".repeat(line_no.len());
+        let newlines = ['\n', '\u{0085}', '\u{2028}', '\u{2029}'];
+        let before = &src[..self.span.start.byte.min(src.len())];
+        let line_start = match before.rfind(newlines) {
+            Some(pos) => pos + before[pos..].chars().next().map_or(1, char::len_utf8),
+            None => 0,
+        };
+        let line = src[line_start..].split(newlines).next().unwrap_or_default();
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let n_carets = if self.span.end.line == self.span.start.line {
+            self.span.end.col - self.span.start.col
+        } else {
+            line.chars().count() - self.span.start.col
+        };
+
+        let mut ans = String::new();
+        ans.push_str(&format!("error: {}\n", self.kind));
+        ans.push_str(&format!(
+            "{}--> {}:{}\n",
+            gutter,
+            line_no,
+            self.span.start.col + 1
+        ));
+        ans.push_str(&format!("{} |\n", gutter));
+        // Tabs are shown as a single space so that the carets line up
+        ans.push_str(&format!("{} | {}\n", line_no, line.replace('\t', " ")));
+        ans.push_str(&format!(
+            "{} | {}{}\n",
+            gutter,
+            " ".repeat(self.span.start.col),
+            "^".repeat(n_carets.max(1))
+        ));
+        if !self.expected.is_empty() {
+            let expected: Vec<String> = self
+                .expected
+                .iter()
+                .map(
+                    |item| match item.chars().all(|ch| ch.is_ascii_lowercase()) {
+                        // Descriptions like "value" instead of actual tokens
+                        true => item.to_string(),
+                        false => format!("`{}`", item),
+                    },
+                )
+                .collect();
+            ans.push_str(&format!(
+                "{} = expected {}\n",
+                gutter,
+                expected.join(" or ")
+            ));
+        }
+        ans
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.start.line,
+            self.span.start.col + 1,
+            self.kind
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
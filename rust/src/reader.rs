@@ -0,0 +1,974 @@
+// Reads a CPTML stream as a sequence of events instead of a whole syntax tree, so that large
+// inputs can be processed without keeping them in memory. The events are either pulled from an
+// io::Read (EventReader) or the input is pushed in chunks as it arrives (EventParser). Only the
+// current event is buffered, so memory use is bounded by the longest run of text, code or tag head.
+//
+// The events cover the input exactly: concatenating their sources gives back the stream. Like
+// parse_document, the stream is parsed in the dialect of its header (see dialect::detect_dialect).
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use nom::IResult;
+
+use crate::ast::{comment_syntax, curly_tag_head, is_text_delimiter, line_start_node, node};
+use crate::ast::{current_dialect, with_context, ParseOptions};
+use crate::ast::{parse_special_char, pointy_tag_start, IdFullName, Node, Rebase, TagAttrValue};
+use crate::attrs::AttrMap;
+use crate::diagnostics::{closes, diagnose_failure, diagnose_node};
+use crate::dialect::{detect_dialect, skip_preamble, Dialect};
+use crate::pos::{Position, Span};
+use crate::prelude::{CptmlError, CptmlResult, ParseError, ParseErrorKind};
+
+const CHUNK_SIZE: usize = 8 * 1024;
+
+// Names borrow nothing from the input, e.g. "tei:line"
+pub type Name = IdFullName<'static>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    // The head of a curly tag, e.g. "{b;" or the "{br" of "{br}"
+    StartElement(Name),
+    // The "}" of a curly tag
+    EndElement(Name),
+    // E.g. "<(t)line n=1|"
+    ViewStart { view: String, name: Name },
+    // E.g. "|(t)line>". Abbreviated ends (e.g. "|(t)>") get the name of the element they close.
+    ViewEnd { view: String, name: Name },
+    // Decoded text (see ast::InlineText::meaning)
+    Text(String),
+    Comment,
+    Code { lang: Option<String>, code: String },
+    Math { tex: String, display: bool },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    kind: EventKind,
+    src: String,
+    span: Span,
+    // Of the stream, which the attributes are parsed in
+    dialect: Dialect,
+}
+
+impl Event {
+    pub fn kind(&self) -> &EventKind {
+        &self.kind
+    }
+
+    // The event exactly as it appeared in the stream
+    pub fn src(&self) -> &str {
+        &self.src
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    // The dialect of the stream the event came from (see dialect::detect_dialect)
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    // The attributes of StartElement and ViewStart events (empty for the others)
+    pub fn attrs(&self) -> AttrMap<'_> {
+        let parsed = with_context(self.dialect, &ParseOptions::default(), || match self.kind {
+            EventKind::StartElement(_) => {
+                curly_tag_head(&self.src).map(|(_, tag)| tag.args().clone())
+            }
+            EventKind::ViewStart { .. } => {
+                pointy_tag_start(&self.src).map(|(_, tag)| tag.args().clone())
+            }
+            _ => Ok(AttrMap::default()),
+        });
+        // The source was already parsed once, so this can't fail
+        let mut attrs = parsed.unwrap_or_default();
+        attrs.rebase(self.span.start);
+        attrs
+    }
+
+    // The value of a StartElement or ViewStart attribute, chosen by the default duplicate policy
+    pub fn attr(&self, name: &str) -> Option<TagAttrValue<'_>> {
+        let attrs = self.attrs();
+        let attr = attrs.get_attr(name).ok()??;
+        attr.value().cloned()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct OpenElement {
+    name: Name,
+    // Of the "{name" or of the whole pointy tag
+    span: Span,
+}
+
+// A push parser: the input is given in chunks of any size with feed and every event that is
+// complete is returned at once, so that it never has to wait for more input.
+#[derive(Debug, Clone, Default)]
+pub struct EventParser {
+    // The part of the input that was fed but not turned into events is buf[start..]. The events are
+    // only removed from buf once in a while, so that the rest isn't moved for every event.
+    buf: String,
+    start: usize,
+    // Where buf[start..] starts in the input
+    pos: Position,
+    // How much of buf[start..] is known not to end the next event (see scan_event)
+    checked: usize,
+    // Bytes after the last complete char of buf, e.g. the first byte of "ç"
+    partial: Vec<u8>,
+    // Set by finish or by invalid UTF-8
+    eof: bool,
+    // Reported once everything before the invalid bytes was parsed
+    utf8_error: bool,
+    // Found after some events of the same feed, so it is reported in the next call
+    error: Option<ParseError>,
+    // Set after an error or at the end of the input
+    done: bool,
+    // Known once the header (or whatever starts the input instead) was read
+    dialect: Option<Dialect>,
+    curly: Vec<OpenElement>,
+    views: HashMap<String, Vec<OpenElement>>,
+}
+
+impl EventParser {
+    pub fn new() -> EventParser {
+        EventParser::default()
+    }
+
+    // The curly tags that are open, from the outermost to the innermost
+    pub fn open_elements(&self) -> Vec<&Name> {
+        self.curly.iter().map(|item| &item.name).collect()
+    }
+
+    // The elements of a view ("" for the default one) that are open, from the outermost to the
+    // innermost
+    pub fn open_view_elements(&self, view: &str) -> Vec<&Name> {
+        match self.views.get(view) {
+            Some(stack) => stack.iter().map(|item| &item.name).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Returns the events that the chunk completes. Chars, escapes, tags and text may be split
+    // anywhere between chunks.
+    pub fn feed(&mut self, bytes: &[u8]) -> CptmlResult<Vec<Event>> {
+        if let Some(err) = self.error.take() {
+            return Err(err.into());
+        }
+        self.push_bytes(bytes);
+        self.take_events()
+    }
+
+    // Ends the input. Returns the events that were waiting for more input, or the first problem
+    // with what is left (e.g. an unterminated comment or an element that was never closed).
+    pub fn finish(&mut self) -> CptmlResult<Vec<Event>> {
+        if let Some(err) = self.error.take() {
+            return Err(err.into());
+        }
+        self.end_input();
+        self.take_events()
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        if self.eof {
+            return;
+        }
+        self.partial.extend_from_slice(bytes);
+        let n_valid = match std::str::from_utf8(&self.partial) {
+            Ok(_) => self.partial.len(),
+            Err(err) => {
+                if err.error_len().is_some() {
+                    // Whatever comes after the invalid bytes is never parsed
+                    self.eof = true;
+                    self.utf8_error = true;
+                }
+                err.valid_up_to()
+            }
+        };
+        // The prefix was just checked
+        let valid = std::str::from_utf8(&self.partial[..n_valid]).unwrap_or_default();
+        if self.start > self.buf.len() / 2 {
+            self.buf.drain(..self.start);
+            self.start = 0;
+        }
+        self.buf.push_str(valid);
+        self.partial.drain(..n_valid);
+    }
+
+    fn end_input(&mut self) {
+        if !self.eof {
+            self.eof = true;
+            // The input ends in the middle of a char
+            self.utf8_error = !self.partial.is_empty();
+        }
+    }
+
+    fn take_events(&mut self) -> CptmlResult<Vec<Event>> {
+        let mut events = Vec::new();
+        loop {
+            match self.next_event() {
+                Ok(Some(event)) => events.push(event),
+                Ok(None) => return Ok(events),
+                // The events before the error are not lost
+                Err(err) if !events.is_empty() => {
+                    self.error = Some(err);
+                    return Ok(events);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    // Returns None if the next event needs more input or at the end of the input
+    fn next_event(&mut self) -> Result<Option<Event>, ParseError> {
+        if self.done {
+            return Ok(None);
+        }
+        let ans = match (self.rest().is_empty(), self.eof) {
+            (true, true) => self.check_end().map(|_| None),
+            (true, false) => Ok(None),
+            (false, _) => self.parse_event(),
+        };
+        self.done = ans.is_err() || (self.eof && matches!(ans, Ok(None)));
+        ans
+    }
+
+    fn rest(&self) -> &str {
+        &self.buf[self.start..]
+    }
+
+    // The events are parsed in the dialect of the header, so nothing is parsed before it was read
+    fn parse_event(&mut self) -> Result<Option<Event>, ParseError> {
+        let dialect = match self.dialect {
+            Some(dialect) => dialect,
+            None => match self.detect_dialect()? {
+                Some(dialect) => dialect,
+                None => return Ok(None),
+            },
+        };
+        with_context(dialect, &ParseOptions::default(), || {
+            self.parse_dialect_event()
+        })
+    }
+
+    // Returns None if the input is not enough to see the whole header yet
+    fn detect_dialect(&mut self) -> Result<Option<Dialect>, ParseError> {
+        let input = self.rest();
+        let head = skip_preamble(input);
+        // E.g. "$$ x" could still be TeX code before the header
+        let may_be_header = ["{!cptml", "{.cptml", "{%", "$", "`"]
+            .iter()
+            .any(|start| start.starts_with(head) || head.starts_with(start));
+        if !self.eof && may_be_header && !is_complete_head(head) {
+            return Ok(None);
+        }
+        let dialect = match detect_dialect(input) {
+            Ok(dialect) => dialect,
+            Err(CptmlError::Parse(err)) => return Err(err),
+            Err(_) => Dialect::LATEST,
+        };
+        self.dialect = Some(dialect);
+        Ok(Some(dialect))
+    }
+
+    // Returns None if the buffered input is not enough to know what the next event is
+    fn parse_dialect_event(&mut self) -> Result<Option<Event>, ParseError> {
+        let input = &self.buf[self.start..];
+        // Long events (e.g. text or code) are not parsed again for every chunk
+        if let (false, Some(checked)) = (self.eof, scan_event(input, self.checked)) {
+            self.checked = checked;
+            return Ok(None);
+        }
+        let parsed = match input.chars().next() {
+            Some('}') => return self.end_element().map(Some),
+            Some('{') if comment_syntax(input).is_none() => curly_start(input),
+            // See ast::line_start_text
+            _ if self.pos.col == 0 => {
+                line_start_node(input).map(|(rest, node)| (rest, event_kind(node)))
+            }
+            _ => node(input).map(|(rest, node)| (rest, event_kind(node))),
+        };
+        let (rest, kind) = match parsed {
+            // Text, numbers and fences could go on in the next chunk
+            Ok((rest, _)) if rest.is_empty() && !self.eof => {
+                self.checked = input.len();
+                return Ok(None);
+            }
+            // E.g. the "<" of "a < b" in version 1
+            Ok((rest, EventKind::Text(_))) if !self.eof && may_be_text(input, rest) => {
+                self.checked = input.len() - rest.len();
+                return Ok(None);
+            }
+            Ok(ans) => ans,
+            Err(err) => {
+                let err = match err {
+                    nom::Err::Failure(err) => diagnose_failure(input, err),
+                    _ => diagnose_node(input, input),
+                };
+                if !self.eof && may_need_more(&err, input) {
+                    // Text is scanned again from the escape that may be incomplete
+                    self.checked = match is_text(input) {
+                        true => err.span().start.byte,
+                        false => input.len(),
+                    };
+                    return Ok(None);
+                }
+                return Err(err.rebase(self.pos));
+            }
+        };
+        let n_bytes = input.len() - rest.len();
+        let event = self.take_event(kind, n_bytes);
+        match &event.kind {
+            EventKind::StartElement(name) => {
+                // E.g. "{b" or, with a view, "{(v)b"
+                let head_len = event
+                    .src
+                    .find(|ch: char| ch.is_whitespace() || ch == ';' || ch == '}')
+                    .unwrap_or(event.src.len());
+                let head = &event.src[..head_len];
+                self.curly.push(OpenElement {
+                    name: name.clone(),
+                    span: Span::new_from_to(event.span.start, event.span.start.after(head)),
+                })
+            }
+            EventKind::ViewStart { view, name } => self
+                .views
+                .entry(view.clone())
+                .or_default()
+                .push(OpenElement {
+                    name: name.clone(),
+                    span: event.span,
+                }),
+            EventKind::ViewEnd { .. } => return self.end_view(event).map(Some),
+            _ => {}
+        }
+        Ok(Some(event))
+    }
+
+    // Turns the first n_bytes of the rest of buf into an event
+    fn take_event(&mut self, kind: EventKind, n_bytes: usize) -> Event {
+        let src = self.buf[self.start..self.start + n_bytes].to_string();
+        self.start += n_bytes;
+        self.checked = 0;
+        let end = self.pos.after(&src);
+        let span = Span::new_from_to(self.pos, end);
+        self.pos = end;
+        Event {
+            kind: kind,
+            src: src,
+            span: span,
+            dialect: self.dialect.unwrap_or(Dialect::LATEST),
+        }
+    }
+
+    fn end_element(&mut self) -> Result<Event, ParseError> {
+        match self.curly.pop() {
+            Some(open) => Ok(self.take_event(EventKind::EndElement(open.name), 1)),
+            None => {
+                let span = Span::new_from_to(self.pos, self.pos.after("}"));
+                Err(ParseError::new(ParseErrorKind::UnmatchedCurlyClose, span))
+            }
+        }
+    }
+
+    // Pointy tags of the same view must be properly nested (see diagnostics::view_errors)
+    fn end_view(&mut self, mut event: Event) -> Result<Event, ParseError> {
+        let (view, name) = match &mut event.kind {
+            EventKind::ViewEnd { view, name } => (view.clone(), name),
+            _ => return Ok(event),
+        };
+        let stack = self.views.entry(view.clone()).or_default();
+        let kind = match stack.last() {
+            Some(open) if name.localname().is_empty() || closes(name, &open.name) => {
+                *name = open.name.clone();
+                stack.pop();
+                return Ok(event);
+            }
+            Some(open) => ParseErrorKind::MismatchedViewClose {
+                view: view,
+                element: name.encode_cptml(),
+                open: open.name.encode_cptml(),
+            },
+            None => ParseErrorKind::UnknownViewClose {
+                view: view,
+                element: name.encode_cptml(),
+            },
+        };
+        Err(ParseError::new(kind, event.span))
+    }
+
+    // Reports anything left open at the end of the stream
+    fn check_end(&self) -> Result<(), ParseError> {
+        if self.utf8_error {
+            let span = Span::new_from(self.pos);
+            return Err(ParseError::new(ParseErrorKind::InvalidUtf8, span));
+        }
+        if let Some(open) = self.curly.last() {
+            let err = ParseError::new(ParseErrorKind::UnterminatedCurlyTag, open.span);
+            return Err(err.expecting(&["}"]));
+        }
+        let unclosed = self
+            .views
+            .iter()
+            .flat_map(|(view, stack)| stack.iter().map(move |open| (view, open)))
+            .min_by_key(|(_, open)| open.span.start.byte);
+        if let Some((view, open)) = unclosed {
+            let end = match view.as_str() {
+                "" => format!("|{}>", open.name.encode_cptml()),
+                view => format!("|({}){}>", view, open.name.encode_cptml()),
+            };
+            let kind = ParseErrorKind::UnclosedViewElement {
+                view: view.clone(),
+                element: open.name.encode_cptml(),
+            };
+            return Err(ParseError::new(kind, open.span).expecting(&[&end]));
+        }
+        Ok(())
+    }
+}
+
+// A pull parser over a stream, e.g. a file. Reads a chunk at a time and only parses as much as
+// needed for the next event (see EventParser).
+pub struct EventReader<R> {
+    src: R,
+    parser: EventParser,
+    // Reused for every read, so that small events don't each fill a new chunk
+    chunk: Box<[u8; CHUNK_SIZE]>,
+}
+
+impl<R: Read> EventReader<R> {
+    pub fn new(src: R) -> EventReader<R> {
+        EventReader {
+            src: src,
+            parser: EventParser::new(),
+            chunk: Box::new([0; CHUNK_SIZE]),
+        }
+    }
+
+    // See EventParser::open_elements
+    pub fn open_elements(&self) -> Vec<&Name> {
+        self.parser.open_elements()
+    }
+
+    pub fn open_view_elements(&self, view: &str) -> Vec<&Name> {
+        self.parser.open_view_elements(view)
+    }
+
+    // Returns None at the end of the stream. After an error, the stream can't be read anymore.
+    pub fn read_event(&mut self) -> CptmlResult<Option<Event>> {
+        loop {
+            if let Some(event) = self.parser.next_event()? {
+                return Ok(Some(event));
+            }
+            if self.parser.done {
+                return Ok(None);
+            }
+            match self.src.read(&mut self.chunk[..]) {
+                Ok(0) => self.parser.end_input(),
+                Ok(n_bytes) => self.parser.push_bytes(&self.chunk[..n_bytes]),
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.parser.done = true;
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for EventReader<R> {
+    type Item = CptmlResult<Event>;
+
+    fn next(&mut self) -> Option<CptmlResult<Event>> {
+        self.read_event().transpose()
+    }
+}
+
+// Parses "{name attrs;" or the "{name attrs" of "{name attrs}"
+fn curly_start(input: &str) -> IResult<&str, EventKind> {
+    let (rest, head) = curly_tag_head(input)?;
+    let name = head.element().clone().into_owned();
+    match rest.chars().next() {
+        Some(';') => Ok((&rest[1..], EventKind::StartElement(name))),
+        Some('}') => Ok((rest, EventKind::StartElement(name))),
+        _ => Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Char,
+        ))),
+    }
+}
+
+fn event_kind(node: Node) -> EventKind {
+    match node {
+        Node::PointyTagStart(tag) => EventKind::ViewStart {
+            view: tag.view().to_string(),
+            name: tag.element().clone().into_owned(),
+        },
+        Node::PointyTagEnd(tag) => EventKind::ViewEnd {
+            view: tag.view().to_string(),
+            name: tag
+                .element()
+                .cloned()
+                .map(IdFullName::into_owned)
+                .unwrap_or_default(),
+        },
+        Node::Text(text) => EventKind::Text(text.meaning().to_string()),
+        Node::CodeBlock(code) => EventKind::Code {
+            lang: Some(code.lang().to_string()).filter(|lang| !lang.is_empty()),
+            code: code.code().to_string(),
+        },
+        Node::TexCode(code) => EventKind::Math {
+            tex: code.meaning().to_string(),
+            display: code.is_display(),
+        },
+        // Curly tags are split into StartElement and EndElement and errors are never parsed
+        Node::Comment(_) | Node::CurlyTag(_) | Node::Error(_) => EventKind::Comment,
+    }
+}
+
+// Whether the text that input starts with goes on in rest once more input comes
+fn may_be_text(input: &str, rest: &str) -> bool {
+    let before = input[..input.len() - rest.len()].chars().next_back();
+    current_dialect().may_be_literal_delimiter(before, rest)
+}
+
+fn is_text(input: &str) -> bool {
+    input
+        .chars()
+        .next()
+        .is_some_and(|ch| !is_text_delimiter(ch))
+}
+
+// Returns how far the event at the start of the input is known to go on, or None if it could end
+// after the first checked bytes and has to be parsed again, e.g. because a "-}" came after
+// "{- a comment". Text is scanned from where the last scan stopped. For the other events, the last
+// checked char is looked at again, since "``code``" could go on as "``code```".
+fn scan_event(input: &str, checked: usize) -> Option<usize> {
+    let ends: &[char] = match input.chars().next() {
+        _ if checked == 0 => return None,
+        Some('{') if comment_syntax(input).is_some() => &['}'],
+        Some('{') => &[';', '}'],
+        Some('<') => &['|'],
+        Some('|') => &['>'],
+        Some('`') => &['`'],
+        Some('$') => &['$'],
+        _ => return scan_text(input, checked),
+    };
+    let from = input[..checked]
+        .char_indices()
+        .next_back()
+        .map_or(0, |(pos, _)| pos);
+    match input[from..].contains(ends) {
+        true => None,
+        false => Some(input.len()),
+    }
+}
+
+// Escaped delimiters don't end text. Escapes that are bad or may be incomplete are left to the
+// parser.
+fn scan_text(input: &str, checked: usize) -> Option<usize> {
+    let mut rest = &input[checked..];
+    while let Some(ch) = rest.chars().next() {
+        rest = match ch {
+            '\\' => match parse_special_char(false, rest) {
+                Ok((rest, _)) => rest,
+                Err(_) if is_escape_prefix(rest) => return Some(input.len() - rest.len()),
+                Err(_) => return None,
+            },
+            ch if is_text_delimiter(ch) => return None,
+            // E.g. the "/*" of a version 1 comment
+            '/' if comment_syntax(rest).is_some() => return None,
+            ch => &rest[ch.len_utf8()..],
+        };
+    }
+    Some(input.len())
+}
+
+// Whether the input could still become an escape, e.g. "\\" or "\\u{1F6"
+fn is_escape_prefix(input: &str) -> bool {
+    match input.strip_prefix("\\u") {
+        Some(hex) => {
+            let hex = hex.strip_prefix('{').unwrap_or(hex);
+            hex.chars().all(|ch| ch.is_ascii_hexdigit())
+        }
+        None => input == "\\",
+    }
+}
+
+// Whether the input has the whole head of the curly tag that starts it, e.g. "{!cptml version=1;"
+// but not "{!cptml version=", or is known not to start with one. As the dialect is not known yet,
+// the input has to be complete in every dialect, e.g. "{.cptml" is only a name in version 1.
+fn is_complete_head(input: &str) -> bool {
+    [Dialect::V1, Dialect::V2].iter().all(|&dialect| {
+        with_context(dialect, &ParseOptions::default(), || {
+            let parsed = curly_tag_head(input);
+            match parsed {
+                Ok((rest, _)) if rest.starts_with([';', '}']) => true,
+                _ => !may_need_more(&diagnose_node(input, input), input),
+            }
+        })
+    })
+}
+
+// Whether an error could go away with more input, e.g. "{- abc" without the "-}" yet
+fn may_need_more(err: &ParseError, input: &str) -> bool {
+    match err.kind() {
+        ParseErrorKind::UnterminatedComment
+        | ParseErrorKind::UnterminatedCurlyTag
+        | ParseErrorKind::UnterminatedPointyTag
+        | ParseErrorKind::UnterminatedCodeBlock
+        | ParseErrorKind::UnterminatedTexCode
+        | ParseErrorKind::UnterminatedString
+        | ParseErrorKind::UnexpectedEnd => true,
+        ParseErrorKind::BadEscape(_) => is_escape_prefix(&input[err.span().start.byte..]),
+        _ => err.span().end.byte >= input.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{idfullname, TagAttrValue};
+    use crate::pos::Span;
+    use crate::prelude::{CptmlError, ParseErrorKind};
+    use crate::reader::*;
+
+    // Gives the input a few bytes at a time, so that events are split between chunks
+    struct Trickle<'a> {
+        src: &'a [u8],
+        step: usize,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n_bytes = self.step.min(self.src.len()).min(buf.len());
+            buf[..n_bytes].copy_from_slice(&self.src[..n_bytes]);
+            self.src = &self.src[n_bytes..];
+            Ok(n_bytes)
+        }
+    }
+
+    fn events(src: &str, step: usize) -> CptmlResult<Vec<Event>> {
+        let reader = EventReader::new(Trickle {
+            src: src.as_bytes(),
+            step: step,
+        });
+        reader.collect()
+    }
+
+    fn name(name: &str) -> Name {
+        idfullname(name).unwrap().1.into_owned()
+    }
+
+    #[test]
+    fn test_event_reader() {
+        let src = "{p n=1; Hi \\{ {br}\n {- c -}$x$}<(t)tei:line|ç``rust\nf()``|(t)>";
+        for step in 1..src.len() + 1 {
+            let events = events(src, step).unwrap();
+            let kinds: Vec<&EventKind> = events.iter().map(|event| event.kind()).collect();
+            assert_eq!(
+                kinds,
+                vec![
+                    &EventKind::StartElement(name("p")),
+                    &EventKind::Text(" Hi { ".to_string()),
+                    &EventKind::StartElement(name("br")),
+                    &EventKind::EndElement(name("br")),
+                    &EventKind::Text("\n".to_string()),
+                    &EventKind::Comment,
+                    &EventKind::Math {
+                        tex: "x".to_string(),
+                        display: false
+                    },
+                    &EventKind::EndElement(name("p")),
+                    &EventKind::ViewStart {
+                        view: "t".to_string(),
+                        name: name("tei:line")
+                    },
+                    &EventKind::Text("ç".to_string()),
+                    &EventKind::Code {
+                        lang: Some("rust".to_string()),
+                        code: "f()".to_string()
+                    },
+                    &EventKind::ViewEnd {
+                        view: "t".to_string(),
+                        name: name("tei:line")
+                    },
+                ],
+                "step = {}",
+                step
+            );
+            let srcs: String = events.iter().map(|event| event.src()).collect();
+            assert_eq!(srcs, src);
+            assert_eq!(events[0].src(), "{p n=1;");
+            assert_eq!(
+                events[0].attr("n"),
+                Some(TagAttrValue::Integer("1".into(), 1))
+            );
+            assert_eq!(
+                events[0]
+                    .attrs()
+                    .iter()
+                    .next()
+                    .unwrap()
+                    .name_span()
+                    .unwrap()
+                    .start
+                    .byte,
+                3
+            );
+            assert_eq!(events[2].src(), "{br");
+            assert_eq!(events[9].span(), Span::new2(44, 2, 25, 46, 2, 26));
+        }
+
+        let src = "{p;{% c; {% d %} %}}";
+        for step in 1..src.len() + 1 {
+            let events = events(src, step).unwrap();
+            let srcs: Vec<&str> = events.iter().map(|event| event.src()).collect();
+            assert_eq!(
+                srcs,
+                vec!["{p;", "{% c; {% d %} %}", "}"],
+                "step = {}",
+                step
+            );
+        }
+
+        // The stream starts a line (see ast::line_start_text)
+        let events = events("   a\n   b", 1).unwrap();
+        assert_eq!(events[0].kind(), &EventKind::Text("a\nb".to_string()));
+    }
+
+    #[test]
+    fn test_event_parser() {
+        let src = "{a x=\"\u{E9}\"; \u{1F600}ã}{- ç -}$$ x$$";
+        let texts = |events: &[Event]| -> Vec<String> {
+            events.iter().map(|event| event.src().to_string()).collect()
+        };
+        // Every way of splitting the input in two chunks, even in the middle of chars
+        for split in 0..src.len() + 1 {
+            let mut parser = EventParser::new();
+            let mut events = parser.feed(&src.as_bytes()[..split]).unwrap();
+            events.extend(parser.feed(&src.as_bytes()[split..]).unwrap());
+            // "$$ x$$" could be the start of "$$ x$$$"
+            assert_eq!(events.len(), 4);
+            events.extend(parser.finish().unwrap());
+            assert_eq!(
+                texts(&events),
+                vec!["{a x=\"\u{E9}\";", " \u{1F600}ã", "}", "{- ç -}", "$$ x$$"],
+                "split = {}",
+                split
+            );
+            assert_eq!(events[1].kind(), &EventKind::Text(" 😀ã".to_string()));
+        }
+
+        // Events are returned as soon as they are complete
+        let mut parser = EventParser::new();
+        assert_eq!(texts(&parser.feed(b"{b; ab").unwrap()), vec!["{b;"]);
+        assert_eq!(parser.open_elements(), vec![&name("b")]);
+        assert_eq!(texts(&parser.feed(b"c}{-").unwrap()), vec![" abc", "}"]);
+        match parser.finish() {
+            Err(CptmlError::Parse(err)) => {
+                assert_eq!(err.kind(), &ParseErrorKind::UnterminatedComment)
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(parser.finish(), Ok(Vec::new()));
+
+        // An error after some events is reported by the next call
+        let mut parser = EventParser::new();
+        assert_eq!(texts(&parser.feed(b"<x|a").unwrap()), vec!["<x|"]);
+        assert_eq!(texts(&parser.feed(b"b|y>c").unwrap()), vec!["ab"]);
+        match parser.feed(b"d") {
+            Err(CptmlError::Parse(err)) => assert_eq!(
+                err.kind(),
+                &ParseErrorKind::MismatchedViewClose {
+                    view: String::new(),
+                    element: "y".to_string(),
+                    open: "x".to_string()
+                }
+            ),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(parser.feed(b"e"), Ok(Vec::new()));
+
+        // Long events are only parsed again when a chunk could end them
+        let mut parser = EventParser::new();
+        assert_eq!(texts(&parser.feed(b"ab").unwrap()), Vec::<String>::new());
+        assert_eq!(texts(&parser.feed(b"cd").unwrap()), Vec::<String>::new());
+        assert_eq!(texts(&parser.feed(b"e{b").unwrap()), vec!["abcde"]);
+        assert_eq!(texts(&parser.feed(b"; ``x``").unwrap()), vec!["{b;", " "]);
+        assert_eq!(texts(&parser.feed(b"}").unwrap()), vec!["``x``", "}"]);
+        let mut parser = EventParser::new();
+        let text = "lorem ipsum, \\{dolor\\} sit amet\\n ".repeat(8 * 1024);
+        for chunk in text.as_bytes().chunks(64) {
+            assert_eq!(parser.feed(chunk).unwrap(), Vec::new());
+        }
+        let events = parser.finish().unwrap();
+        assert_eq!(texts(&events), vec![text]);
+        let mut parser = EventParser::new();
+        assert_eq!(parser.feed(b"a\\u{1F6").unwrap(), Vec::new());
+        let events = parser.feed(b"00}{").unwrap();
+        assert_eq!(events[0].kind(), &EventKind::Text("a\u{1F600}".to_string()));
+        let src = "{b;x}".repeat(64 * 1024);
+        let mut parser = EventParser::new();
+        assert_eq!(parser.feed(src.as_bytes()).unwrap().len(), 3 * 64 * 1024);
+
+        // The input ends in the middle of "ç"
+        let mut parser = EventParser::new();
+        assert_eq!(
+            texts(&parser.feed(&"aç".as_bytes()[..2]).unwrap()),
+            Vec::<String>::new()
+        );
+        assert_eq!(texts(&parser.finish().unwrap()), vec!["a"]);
+        match parser.finish() {
+            Err(CptmlError::Parse(err)) => {
+                assert_eq!(err.kind(), &ParseErrorKind::InvalidUtf8);
+                assert_eq!(err.span(), Span::new2(1, 1, 1, 1, 1, 1));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_event_reader_version_1() {
+        let src = "{.cptml version=1}{a .n=1; /* x */ y {(v)b} c < d \\u41;}";
+        assert!(crate::ast::parse_document(src).is_ok());
+        for step in 1..src.len() + 1 {
+            let events = events(src, step).unwrap();
+            let srcs: Vec<&str> = events.iter().map(|event| event.src()).collect();
+            assert_eq!(
+                srcs,
+                vec![
+                    "{.cptml version=1",
+                    "}",
+                    "{a .n=1;",
+                    " ",
+                    "/* x */",
+                    " y ",
+                    "{(v)b",
+                    "}",
+                    " c < d \\u41;",
+                    "}"
+                ],
+                "step = {}",
+                step
+            );
+            assert!(events.iter().all(|event| event.dialect() == Dialect::V1));
+            match events[2].kind() {
+                EventKind::StartElement(name) => assert_eq!(name.full_name(), "a"),
+                other => panic!("{:?}", other),
+            }
+            assert_eq!(
+                events[2].attr("!n"),
+                Some(TagAttrValue::Integer("1".into(), 1))
+            );
+            assert_eq!(events[4].kind(), &EventKind::Comment);
+            assert_eq!(events[8].kind(), &EventKind::Text(" c < d A".to_string()));
+        }
+
+        // Comments, code blocks and TeX code may come before the header
+        let src = "$$ x $$\n{% a %} {.cptml version=1}/* b */";
+        for step in 1..src.len() + 1 {
+            let events = events(src, step).unwrap();
+            assert!(events.iter().all(|event| event.dialect() == Dialect::V1));
+            assert_eq!(events.last().unwrap().kind(), &EventKind::Comment);
+        }
+
+        // Streams without a header are in the latest dialect
+        let events = events("  {a; /* x */}", 1).unwrap();
+        assert!(events
+            .iter()
+            .all(|event| event.dialect() == Dialect::LATEST));
+        assert_eq!(events[2].kind(), &EventKind::Text(" /* x */".to_string()));
+    }
+
+    #[test]
+    fn test_event_reader_stacks() {
+        let mut reader = EventReader::new("{a;<(t)x|<y|{b;|(t)>".as_bytes());
+        for _ in 0..4 {
+            reader.read_event().unwrap();
+        }
+        assert_eq!(reader.open_elements(), vec![&name("a"), &name("b")]);
+        assert_eq!(reader.open_view_elements("t"), vec![&name("x")]);
+        assert_eq!(reader.open_view_elements(""), vec![&name("y")]);
+        reader.read_event().unwrap();
+        assert!(reader.open_view_elements("t").is_empty());
+    }
+
+    #[test]
+    fn test_event_reader_errors() {
+        let err = |src: &str| match events(src, 3) {
+            Err(CptmlError::Parse(err)) => (err.kind().clone(), err.span()),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(
+            err("a}"),
+            (
+                ParseErrorKind::UnmatchedCurlyClose,
+                Span::new2(1, 1, 1, 2, 1, 2)
+            )
+        );
+        assert_eq!(
+            err("{b; abc"),
+            (
+                ParseErrorKind::UnterminatedCurlyTag,
+                Span::new2(0, 1, 0, 2, 1, 2)
+            )
+        );
+        assert_eq!(
+            err("ab {- abc"),
+            (
+                ParseErrorKind::UnterminatedComment,
+                Span::new2(3, 1, 3, 5, 1, 5)
+            )
+        );
+        assert_eq!(
+            err("<(t)a|<(t)b||(t)a>"),
+            (
+                ParseErrorKind::MismatchedViewClose {
+                    view: "t".to_string(),
+                    element: "a".to_string(),
+                    open: "b".to_string()
+                },
+                Span::new2(12, 1, 12, 18, 1, 18)
+            )
+        );
+        assert_eq!(
+            err("<(t)a|"),
+            (
+                ParseErrorKind::UnclosedViewElement {
+                    view: "t".to_string(),
+                    element: "a".to_string()
+                },
+                Span::new2(0, 1, 0, 6, 1, 6)
+            )
+        );
+        assert_eq!(
+            err("x\\q"),
+            (
+                ParseErrorKind::BadEscape("\\q".to_string()),
+                Span::new2(1, 1, 1, 3, 1, 3)
+            )
+        );
+
+        assert_eq!(
+            err("{!cptml version=9}"),
+            (
+                ParseErrorKind::UnknownVersion("9".to_string()),
+                Span::new2(16, 1, 16, 17, 1, 17)
+            )
+        );
+        // The events before an error are still read
+        let mut reader = EventReader::new(&b"ab\xffcd"[..]);
+        assert_eq!(
+            reader.next().unwrap().unwrap().kind(),
+            &EventKind::Text("ab".to_string())
+        );
+        match reader.next() {
+            Some(Err(CptmlError::Parse(err))) => {
+                assert_eq!(err.kind(), &ParseErrorKind::InvalidUtf8);
+                assert_eq!(err.span(), Span::new2(2, 1, 2, 2, 1, 2));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,369 @@
+// The second pass: turns the syntax tree from ast.rs into what the document means. Comments and
+// error nodes are dropped, text is decoded and attribute values are typed. Unlike the syntax tree,
+// the result can't be encoded back into the exact source, so each node links to its syntax node.
+
+use crate::ast::{self, IdFullName, IriRef, TagAttr, TagAttrValue};
+use crate::attrs::{AttrMap, DuplicateAttr};
+use crate::decimal::Decimal;
+use crate::pos::Span;
+
+// Names of the virtual nodes, which can't clash with element names
+pub const TEXT: &str = ".text";
+pub const WHITESPACE: &str = ".whitespace";
+pub const CODE: &str = ".code";
+pub const MATH: &str = ".math";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value<'a> {
+    Boolean(bool),
+    Integer(i64),
+    Decimal(Decimal),
+    String(String),
+    Url(IriRef),
+    List(Vec<Value<'a>>),
+    Dict(Vec<(String, Value<'a>)>),
+    Nodes(Vec<Node<'a>>),
+}
+
+impl<'a> Value<'a> {
+    fn resolve(value: &'a TagAttrValue<'a>) -> Value<'a> {
+        match value {
+            TagAttrValue::Boolean(_, val) => Value::Boolean(*val),
+            TagAttrValue::Integer(_, val) => Value::Integer(*val),
+            TagAttrValue::Decimal(_, val) => Value::Decimal(val.clone()),
+            TagAttrValue::String(_, val) => Value::String(val.clone()),
+            TagAttrValue::Url(_, val) => Value::Url(val.clone()),
+            TagAttrValue::List(_, items) => Value::List(items.iter().map(Value::resolve).collect()),
+            TagAttrValue::Dict(_, entries) => Value::Dict(
+                entries
+                    .iter()
+                    .map(|(key, val)| (key.clone(), Value::resolve(val)))
+                    .collect(),
+            ),
+            TagAttrValue::Nodes(_, nodes) => Value::Nodes(resolve_nodes(nodes)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attr<'a> {
+    name: Option<IdFullName<'a>>,
+    value: Option<Value<'a>>,
+    syntax: &'a TagAttr<'a>,
+}
+
+impl<'a> Attr<'a> {
+    fn resolve(attr: &'a TagAttr<'a>) -> Attr<'a> {
+        Attr {
+            name: attr.name().cloned(),
+            value: attr.value().map(Value::resolve),
+            syntax: attr,
+        }
+    }
+
+    // None for positional attributes
+    pub fn name(&self) -> Option<&IdFullName<'a>> {
+        self.name.as_ref()
+    }
+
+    // None for bare names, e.g. "hidden"
+    pub fn value(&self) -> Option<&Value<'a>> {
+        self.value.as_ref()
+    }
+
+    pub fn syntax(&self) -> &'a TagAttr<'a> {
+        self.syntax
+    }
+}
+
+fn resolve_attrs<'a>(map: &'a AttrMap<'a>) -> Vec<Attr<'a>> {
+    map.iter().map(Attr::resolve).collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind<'a> {
+    // A curly tag, e.g. "{b; text}"
+    Element {
+        name: IdFullName<'a>,
+        attrs: Vec<Attr<'a>>,
+        children: Vec<Node<'a>>,
+    },
+    // Pointy tags stay as milestones, since elements of different views may overlap
+    ViewStart {
+        view: &'a str,
+        name: IdFullName<'a>,
+        attrs: Vec<Attr<'a>>,
+    },
+    ViewEnd {
+        view: &'a str,
+        name: Option<IdFullName<'a>>,
+    },
+    // Decoded text (see ast::InlineText::meaning)
+    Text(String),
+    // Text that is only unescaped whitespace in the source, e.g. the indentation between tags
+    Whitespace(String),
+    Code {
+        lang: Option<&'a str>,
+        code: &'a str,
+    },
+    Math {
+        tex: &'a str,
+        display: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node<'a> {
+    kind: NodeKind<'a>,
+    syntax: &'a ast::Node<'a>,
+}
+
+impl<'a> Node<'a> {
+    fn resolve(node: &'a ast::Node<'a>) -> Option<Node<'a>> {
+        let kind = match node {
+            ast::Node::CurlyTag(tag) => NodeKind::Element {
+                name: tag.start().element().clone(),
+                attrs: resolve_attrs(tag.start().args()),
+                children: resolve_nodes(tag.content()),
+            },
+            ast::Node::PointyTagStart(tag) => NodeKind::ViewStart {
+                view: tag.view(),
+                name: tag.element().clone(),
+                attrs: resolve_attrs(tag.args()),
+            },
+            ast::Node::PointyTagEnd(tag) => NodeKind::ViewEnd {
+                view: tag.view(),
+                name: tag.element().cloned(),
+            },
+            ast::Node::Text(text) if text.meaning().is_empty() => return None,
+            ast::Node::Text(text) if text.src().chars().all(char::is_whitespace) => {
+                NodeKind::Whitespace(text.meaning().to_string())
+            }
+            ast::Node::Text(text) => NodeKind::Text(text.meaning().to_string()),
+            ast::Node::CodeBlock(code) => NodeKind::Code {
+                lang: Some(code.lang()).filter(|lang| !lang.is_empty()),
+                code: code.code(),
+            },
+            ast::Node::TexCode(code) => NodeKind::Math {
+                tex: code.meaning(),
+                display: code.is_display(),
+            },
+            ast::Node::Comment(_) | ast::Node::Error(_) => return None,
+        };
+        Some(Node {
+            kind: kind,
+            syntax: node,
+        })
+    }
+
+    pub fn kind(&self) -> &NodeKind<'a> {
+        &self.kind
+    }
+
+    pub fn syntax(&self) -> &'a ast::Node<'a> {
+        self.syntax
+    }
+
+    pub fn span(&self) -> Span {
+        self.syntax.span()
+    }
+
+    // Element names (e.g. "tei:line") or the names of virtual nodes (e.g. ".text"). Names are
+    // spelled as in the latest dialect, e.g. "tei.line" in a version 1 document is "tei:line".
+    pub fn name(&self) -> String {
+        match &self.kind {
+            NodeKind::Element { name, .. } | NodeKind::ViewStart { name, .. } => name.full_name(),
+            NodeKind::ViewEnd { name, .. } => name
+                .as_ref()
+                .map(|name| name.full_name())
+                .unwrap_or_default(),
+            NodeKind::Text(_) => TEXT.to_string(),
+            NodeKind::Whitespace(_) => WHITESPACE.to_string(),
+            NodeKind::Code { .. } => CODE.to_string(),
+            NodeKind::Math { .. } => MATH.to_string(),
+        }
+    }
+
+    pub fn attrs(&self) -> &[Attr<'a>] {
+        match &self.kind {
+            NodeKind::Element { attrs, .. } | NodeKind::ViewStart { attrs, .. } => attrs,
+            _ => &[],
+        }
+    }
+
+    pub fn children(&self) -> &[Node<'a>] {
+        match &self.kind {
+            NodeKind::Element { children, .. } => children,
+            _ => &[],
+        }
+    }
+
+    // The text of text and whitespace nodes
+    pub fn text(&self) -> Option<&str> {
+        match &self.kind {
+            NodeKind::Text(text) | NodeKind::Whitespace(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    // The value chosen by the duplicate policy of the tag (see AttrMap::get_attr)
+    pub fn attr(&self, name: &str) -> Result<Option<&Value<'a>>, DuplicateAttr> {
+        let map = match self.syntax {
+            ast::Node::CurlyTag(tag) => tag.start().args(),
+            ast::Node::PointyTagStart(tag) => tag.args(),
+            _ => return Ok(None),
+        };
+        let chosen = match map.get_attr(name)? {
+            Some(chosen) => chosen,
+            None => return Ok(None),
+        };
+        Ok(self
+            .attrs()
+            .iter()
+            .find(|attr| std::ptr::eq(attr.syntax, chosen))
+            .and_then(|attr| attr.value()))
+    }
+}
+
+fn resolve_nodes<'a>(nodes: &'a [ast::Node<'a>]) -> Vec<Node<'a>> {
+    nodes.iter().filter_map(Node::resolve).collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document<'a> {
+    nodes: Vec<Node<'a>>,
+    syntax: &'a ast::Document<'a>,
+}
+
+impl<'a> Document<'a> {
+    pub fn nodes(&self) -> &[Node<'a>] {
+        &self.nodes
+    }
+
+    pub fn syntax(&self) -> &'a ast::Document<'a> {
+        self.syntax
+    }
+}
+
+pub fn resolve_document<'a>(doc: &'a ast::Document<'a>) -> Document<'a> {
+    Document {
+        nodes: resolve_nodes(doc.nodes()),
+        syntax: doc,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{parse_document, parse_document_with, ParseOptions};
+    use crate::attrs::DuplicatePolicy;
+    use crate::semantic::*;
+
+    fn names(nodes: &[Node]) -> Vec<String> {
+        nodes.iter().map(|node| node.name()).collect()
+    }
+
+    #[test]
+    fn test_resolve_document() {
+        let src =
+            "{p n=1 {- x -} \" \"; a\\tb {- c -}\n  {b;\\{d\\}}}\n$$ x^2$$ ``rust\nfn f() {}``";
+        let syntax = parse_document(src).unwrap();
+        let doc = resolve_document(&syntax);
+        assert_eq!(
+            names(doc.nodes()),
+            vec!["p", ".whitespace", ".math", ".whitespace", ".code"]
+        );
+
+        let p = &doc.nodes()[0];
+        assert!(std::ptr::eq(p.syntax(), &syntax.nodes()[0]));
+        assert_eq!(p.span(), syntax.nodes()[0].span());
+        assert_eq!(p.attr("n"), Ok(Some(&Value::Integer(1))));
+        assert_eq!(p.attrs()[1].name(), None);
+        assert_eq!(p.attrs()[1].value(), Some(&Value::String(" ".to_string())));
+        assert_eq!(names(p.children()), vec![".text", ".whitespace", "b"]);
+        assert_eq!(p.children()[0].text(), Some(" a\tb "));
+        assert_eq!(p.children()[1].text(), Some("\n"));
+        assert_eq!(p.children()[2].children()[0].text(), Some("{d}"));
+
+        assert_eq!(
+            doc.nodes()[2].kind(),
+            &NodeKind::Math {
+                tex: "x^2",
+                display: true
+            }
+        );
+        assert_eq!(
+            doc.nodes()[4].kind(),
+            &NodeKind::Code {
+                lang: Some("rust"),
+                code: "fn f() {}"
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_attrs() {
+        let src = r#"{a x=1.5 y=[1, "b"] z={k: <#top>} w=<>{i; hi}</> x=true flag;}"#;
+        let options = ParseOptions {
+            duplicate_policy: DuplicatePolicy::FirstWins,
+            ..ParseOptions::default()
+        };
+        let syntax = parse_document_with(src, &options).unwrap();
+        let doc = resolve_document(&syntax);
+        let a = &doc.nodes()[0];
+        assert_eq!(
+            a.attr("x"),
+            Ok(Some(&Value::Decimal("1.5".parse().unwrap())))
+        );
+        assert_eq!(
+            a.attr("y"),
+            Ok(Some(&Value::List(vec![
+                Value::Integer(1),
+                Value::String("b".to_string())
+            ])))
+        );
+        assert_eq!(
+            a.attr("z"),
+            Ok(Some(&Value::Dict(vec![(
+                "k".to_string(),
+                Value::Url(IriRef::Fragment("top".to_string()))
+            )])))
+        );
+        match a.attr("w") {
+            Ok(Some(Value::Nodes(nodes))) => {
+                assert_eq!(names(nodes), vec!["i"]);
+                assert_eq!(nodes[0].children()[0].text(), Some(" hi"));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(a.attr("flag"), Ok(None));
+        assert_eq!(a.attrs()[5].name().unwrap().encode_cptml(), "flag");
+        assert_eq!(a.attr("missing"), Ok(None));
+    }
+
+    #[test]
+    fn test_resolve_views() {
+        let syntax = parse_document("<(t)line n=1|a|(t)line>").unwrap();
+        let doc = resolve_document(&syntax);
+        assert_eq!(names(doc.nodes()), vec!["line", ".text", "line"]);
+        assert_eq!(doc.nodes()[0].attr("n"), Ok(Some(&Value::Integer(1))));
+        match doc.nodes()[2].kind() {
+            NodeKind::ViewEnd { view, name } => {
+                assert_eq!(*view, "t");
+                assert_eq!(name.as_ref().unwrap().localname(), "line");
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(std::ptr::eq(doc.syntax(), &syntax));
+    }
+
+    #[test]
+    fn test_resolve_version_1() {
+        let syntax = parse_document("{.cptml version=1}{tei.line .id=\"a\"; x}").unwrap();
+        let doc = resolve_document(&syntax);
+        assert_eq!(names(doc.nodes()), vec!["!cptml", "tei:line"]);
+        assert_eq!(
+            doc.nodes()[1].attr("!id"),
+            Ok(Some(&Value::String("a".to_string())))
+        );
+    }
+}
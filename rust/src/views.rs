@@ -0,0 +1,507 @@
+// Pairs the starts and ends of pointy tags. Each view is a hierarchy of its own, e.g. lines in "t"
+// and sentences in "g", so the elements of a view must nest properly but may overlap the ones of
+// other views and the curly tags. Each element covers a range of the text of the document, which
+// is Document::text of the root.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use crate::ast::{CurlyTagStart, IdFullName, PointyTagStart};
+use crate::attrs::AttrMap;
+use crate::diagnostics::closes;
+use crate::dom::{Document, Element, NodeId, NodeKind};
+use crate::pos::Span;
+use crate::prelude::{CptmlResult, ParseError, ParseErrorKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewElement<'d> {
+    syntax: &'d PointyTagStart<'d>,
+    start: NodeId,
+    end: NodeId,
+    // The enclosing element of the same view, as an index of Views::elements
+    parent: Option<usize>,
+    span: Span,
+    text: Range<usize>,
+}
+
+impl<'d> ViewElement<'d> {
+    // "" for the default view
+    pub fn view(&self) -> &'d str {
+        self.syntax.view()
+    }
+
+    pub fn name(&self) -> &'d IdFullName<'d> {
+        self.syntax.element()
+    }
+
+    pub fn attrs(&self) -> &'d AttrMap<'d> {
+        self.syntax.args()
+    }
+
+    pub fn syntax(&self) -> &'d PointyTagStart<'d> {
+        self.syntax
+    }
+
+    // The nodes of the start and end tags
+    pub fn start(&self) -> NodeId {
+        self.start
+    }
+
+    pub fn end(&self) -> NodeId {
+        self.end
+    }
+
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+
+    // From the start of the start tag until the end of the end tag
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    // In bytes of the text of the document
+    pub fn text_range(&self) -> Range<usize> {
+        self.text.clone()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Views<'d> {
+    // In the order of their start tags
+    elements: Vec<ViewElement<'d>>,
+}
+
+impl<'d> Views<'d> {
+    pub fn elements(&self) -> &[ViewElement<'d>] {
+        &self.elements
+    }
+
+    // In the order they first appear
+    pub fn names(&self) -> Vec<&'d str> {
+        let mut ans: Vec<&str> = Vec::new();
+        for element in &self.elements {
+            if !ans.contains(&element.view()) {
+                ans.push(element.view());
+            }
+        }
+        ans
+    }
+
+    pub fn view<'s>(&'s self, view: &'s str) -> impl Iterator<Item = &'s ViewElement<'d>> + 's {
+        self.elements
+            .iter()
+            .filter(move |element| element.view() == view)
+    }
+}
+
+// Fails on the first end tag that doesn't close the innermost open element of its view or, if
+// there is none, on the first element that is never closed
+pub fn resolve_views<'d>(doc: &'d Document<'_>) -> CptmlResult<Views<'d>> {
+    let mut elements: Vec<ViewElement<'d>> = Vec::new();
+    let mut open: HashMap<&str, Vec<usize>> = HashMap::new();
+    let mut text_len = 0;
+    for id in doc.descendants(doc.root()) {
+        match doc.kind(id) {
+            NodeKind::Text(text) => text_len += text.meaning().len(),
+            NodeKind::ViewStart(tag) => {
+                let stack = open.entry(tag.view()).or_default();
+                elements.push(ViewElement {
+                    syntax: tag,
+                    start: id,
+                    // Until the end tag is found
+                    end: id,
+                    parent: stack.last().copied(),
+                    span: doc.span(id),
+                    text: text_len..text_len,
+                });
+                stack.push(elements.len() - 1);
+            }
+            NodeKind::ViewEnd(tag) => {
+                let stack = open.entry(tag.view()).or_default();
+                let closed = match tag.element() {
+                    Some(name) => stack
+                        .iter()
+                        .rposition(|&idx| closes(name, elements[idx].name())),
+                    None => stack.len().checked_sub(1),
+                };
+                let element = tag.element().map(|name| name.encode_cptml());
+                let kind = match (closed, stack.last()) {
+                    (Some(closed), _) if closed + 1 == stack.len() => None,
+                    (Some(_), Some(&innermost)) => Some(ParseErrorKind::MismatchedViewClose {
+                        view: tag.view().to_string(),
+                        element: element.unwrap_or_default(),
+                        open: elements[innermost].name().encode_cptml(),
+                    }),
+                    _ => Some(ParseErrorKind::UnknownViewClose {
+                        view: tag.view().to_string(),
+                        element: element.unwrap_or_default(),
+                    }),
+                };
+                if let Some(kind) = kind {
+                    return Err(ParseError::new(kind, doc.span(id)).into());
+                }
+                if let Some(idx) = stack.pop() {
+                    let element = &mut elements[idx];
+                    element.end = id;
+                    element.span = Span::new_from_to(element.span.start, doc.span(id).end);
+                    element.text.end = text_len;
+                }
+            }
+            _ => {}
+        }
+    }
+    let unclosed = open.values().flatten().min();
+    if let Some(&idx) = unclosed {
+        let element = &elements[idx];
+        let kind = ParseErrorKind::UnclosedViewElement {
+            view: element.view().to_string(),
+            element: element.name().encode_cptml(),
+        };
+        return Err(ParseError::new(kind, element.span).into());
+    }
+    Ok(Views { elements: elements })
+}
+
+// What Document::project_view does with the pointy tags of the other views
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OtherViews {
+    #[default]
+    Drop,
+    // They are kept where they are, as empty markers
+    Milestones,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProjectOptions {
+    pub other_views: OtherViews,
+}
+
+enum Event {
+    Open(NodeId),
+    Close(NodeId),
+    Leaf(NodeId),
+}
+
+fn events(doc: &Document<'_>, id: NodeId, ans: &mut Vec<Event>) {
+    for &child in doc.children(id) {
+        if let NodeKind::Element(_) = doc.kind(child) {
+            ans.push(Event::Open(child));
+            events(doc, child, ans);
+            ans.push(Event::Close(child));
+        } else {
+            ans.push(Event::Leaf(child));
+        }
+    }
+}
+
+// An element of the projection that hasn't been closed yet
+struct Open {
+    id: NodeId,
+    // The curly element or the start tag it comes from
+    source: NodeId,
+    // The index of the event that closes it
+    end: usize,
+    is_view: bool,
+    // Whether it is the second or later part of a split curly element
+    is_copy: bool,
+}
+
+impl<'a> Document<'a> {
+    // A tree in which the elements of the view are elements, e.g. "<t/line n=1|...|t/line>"
+    // becomes "{line n=1;...}". Curly elements that partially overlap an element of the view are
+    // split at its start and end tags, so "{p; <t/a|x}{q; y|t/a>}" becomes
+    // "{p; }{a;{p;x}{q; y}}". Fails like resolve_views.
+    pub fn project_view(&self, view: &str) -> CptmlResult<Document<'a>> {
+        self.project_view_with(view, &ProjectOptions::default())
+    }
+
+    pub fn project_view_with(
+        &self,
+        view: &str,
+        options: &ProjectOptions,
+    ) -> CptmlResult<Document<'a>> {
+        let views = resolve_views(self)?;
+        let mut list = Vec::new();
+        events(self, self.root(), &mut list);
+        let mut end_of: HashMap<NodeId, usize> = HashMap::new();
+        let mut positions: HashMap<NodeId, usize> = HashMap::new();
+        for (pos, event) in list.iter().enumerate() {
+            match event {
+                Event::Close(id) => {
+                    end_of.insert(*id, pos);
+                }
+                Event::Leaf(id) => {
+                    positions.insert(*id, pos);
+                }
+                Event::Open(_) => {}
+            }
+        }
+        let mut ends = HashSet::new();
+        let mut spans = HashMap::new();
+        for element in views.view(view) {
+            end_of.insert(element.start(), positions[&element.end()]);
+            ends.insert(element.end());
+            spans.insert(element.start(), element.span());
+        }
+
+        let mut ans = Document::empty(self.span(self.root()), self.dialect(), self.options());
+        let mut stack: Vec<Open> = Vec::new();
+        for event in &list {
+            let parent = stack.last().map_or(ans.root(), |open| open.id);
+            match *event {
+                Event::Open(id) => {
+                    let child = ans.push_child(parent, self.kind(id).clone(), self.span(id));
+                    stack.push(Open {
+                        id: child,
+                        source: id,
+                        end: end_of[&id],
+                        is_view: false,
+                        is_copy: false,
+                    });
+                }
+                Event::Close(_) => {
+                    close(&mut ans, &mut stack);
+                }
+                Event::Leaf(id) if spans.contains_key(&id) => {
+                    let end = end_of[&id];
+                    // The curly elements that end inside this one
+                    let mut split = Vec::new();
+                    while stack
+                        .last()
+                        .is_some_and(|open| !open.is_view && open.end < end)
+                    {
+                        split.push(stack.pop().unwrap());
+                    }
+                    let start = match self.kind(id) {
+                        NodeKind::ViewStart(tag) => CurlyTagStart::from_pointy(tag),
+                        _ => unreachable!(),
+                    };
+                    let parent = stack.last().map_or(ans.root(), |open| open.id);
+                    let kind = NodeKind::Element(Element::new(start));
+                    let child = ans.push_child(parent, kind, spans[&id]);
+                    stack.push(Open {
+                        id: child,
+                        source: id,
+                        end: end,
+                        is_view: true,
+                        is_copy: false,
+                    });
+                    reopen(self, &mut ans, &mut stack, split);
+                }
+                Event::Leaf(id) if ends.contains(&id) => {
+                    // The curly elements that go on after this one
+                    let mut split = Vec::new();
+                    while stack.last().is_some_and(|open| !open.is_view) {
+                        split.extend(close(&mut ans, &mut stack));
+                    }
+                    close(&mut ans, &mut stack);
+                    reopen(self, &mut ans, &mut stack, split);
+                }
+                Event::Leaf(id) => {
+                    let keep = match self.kind(id) {
+                        NodeKind::ViewStart(_) | NodeKind::ViewEnd(_) => {
+                            options.other_views == OtherViews::Milestones
+                        }
+                        _ => true,
+                    };
+                    if keep {
+                        ans.push_child(parent, self.kind(id).clone(), self.span(id));
+                    }
+                }
+            }
+        }
+        Ok(ans)
+    }
+}
+
+// Closes the innermost open element, leaving out the parts of split elements that got no content
+fn close(doc: &mut Document<'_>, stack: &mut Vec<Open>) -> Option<Open> {
+    let open = stack.pop()?;
+    if open.is_copy && doc.children(open.id).is_empty() {
+        doc.remove(open.id).unwrap();
+    }
+    Some(open)
+}
+
+// Opens the next parts of curly elements split by an element of the view, innermost last. Only
+// the first part keeps the "!id", so that ids stay unique in the projection.
+fn reopen<'a>(src: &Document<'a>, doc: &mut Document<'a>, stack: &mut Vec<Open>, split: Vec<Open>) {
+    for open in split.into_iter().rev() {
+        let parent = stack.last().map_or(doc.root(), |open| open.id);
+        let kind = src.kind(open.source).clone();
+        let child = doc.push_child(parent, kind, src.span(open.source));
+        doc.remove_attr(child, "!id").unwrap();
+        stack.push(Open {
+            id: child,
+            is_copy: true,
+            ..open
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::parse_document_recovering;
+    use crate::ast::{parse_document, ParseOptions};
+    use crate::dom::Document;
+    use crate::prelude::{CptmlError, ParseErrorKind};
+    use crate::views::*;
+
+    fn dom(src: &str) -> Document<'_> {
+        Document::new(parse_document(src).unwrap())
+    }
+
+    #[test]
+    fn test_resolve_views() {
+        let src = concat!(
+            "{poem;\n",
+            "  <t/line n=1|<g/sentence|I, by attorney,|t/line>\n",
+            "  <t/line n=2|who prays.|g/sentence><g/s|So|t/>}",
+            "{p; much|g/s>}"
+        );
+        let doc = dom(src);
+        let text = doc.text(doc.root());
+        let views = resolve_views(&doc).unwrap();
+        assert_eq!(views.names(), ["t", "g"]);
+        let ranges: Vec<(&str, &str)> = views
+            .elements()
+            .iter()
+            .map(|element| (element.view(), &text[element.text_range()]))
+            .collect();
+        assert_eq!(
+            ranges,
+            [
+                ("t", "I, by attorney,"),
+                ("g", "I, by attorney,\nwho prays."),
+                ("t", "who prays.So"),
+                ("g", "So much"),
+            ]
+        );
+        let lines: Vec<_> = views.view("t").collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[1].attrs().get("n").unwrap().unwrap().encode_cptml(),
+            "2"
+        );
+        assert_eq!(lines[1].name().encode_cptml(), "line");
+        assert_eq!(lines[0].span(), Span::new2(9, 2, 2, 56, 2, 49));
+        assert!(matches!(doc.kind(lines[0].start()), NodeKind::ViewStart(_)));
+        assert!(matches!(doc.kind(lines[0].end()), NodeKind::ViewEnd(_)));
+        assert!(views.elements().iter().all(|e| e.parent().is_none()));
+
+        let doc = dom("<(t)a|<(t)b|x|(t)>|(t)a><c|y|>");
+        let views = resolve_views(&doc).unwrap();
+        assert_eq!(views.names(), ["t", ""]);
+        assert_eq!(views.elements()[1].parent(), Some(0));
+        assert_eq!(views.elements()[2].text_range(), 1..2);
+    }
+
+    #[test]
+    fn test_resolve_views_errors() {
+        let opts = ParseOptions::default();
+        for (src, kind) in [
+            (
+                "<(t)a|<(t)b||(t)a>",
+                ParseErrorKind::MismatchedViewClose {
+                    view: "t".to_string(),
+                    element: "a".to_string(),
+                    open: "b".to_string(),
+                },
+            ),
+            (
+                "x|(t)a>",
+                ParseErrorKind::UnknownViewClose {
+                    view: "t".to_string(),
+                    element: "a".to_string(),
+                },
+            ),
+            (
+                "<(g)b|<(t)a||(g)>",
+                ParseErrorKind::UnclosedViewElement {
+                    view: "t".to_string(),
+                    element: "a".to_string(),
+                },
+            ),
+        ] {
+            // The parser rejects the same documents
+            let (ast_doc, errors) = parse_document_recovering(src, &opts);
+            assert_eq!(errors[0].kind(), &kind, "{}", src);
+            let doc = Document::new(ast_doc);
+            match resolve_views(&doc) {
+                Err(CptmlError::Parse(err)) => {
+                    assert_eq!(err.kind(), &kind);
+                    assert_eq!(err.span(), errors[0].span());
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_project_view() {
+        let src = concat!(
+            "{poem;\n",
+            "<t/line|<g/sentence|I, by attorney, bless thee from thy mother,|t/line>\n",
+            "<t/line|Who prays continually for Richmond's good.|g/sentence>|t/line>\n",
+            "<t/line|<g/sentence|So much for that.|/g><g/sentence|—The silent hours steal on,|t/>\n",
+            "<t/line|And flaky darkness breaks within the east.|g/>|t/>\n",
+            "}",
+        );
+        let doc = dom(src);
+        let lines = doc.project_view("t").unwrap();
+        assert_eq!(
+            lines.encode_cptml(),
+            concat!(
+                "{poem;\n",
+                "{line;I, by attorney, bless thee from thy mother,}\n",
+                "{line;Who prays continually for Richmond's good.}\n",
+                "{line;So much for that.—The silent hours steal on,}\n",
+                "{line;And flaky darkness breaks within the east.}\n",
+                "}",
+            )
+        );
+        let sentences = doc.project_view("g").unwrap();
+        assert_eq!(
+            sentences.encode_cptml(),
+            concat!(
+                "{poem;\n",
+                "{sentence;I, by attorney, bless thee from thy mother,\n",
+                "Who prays continually for Richmond's good.}\n",
+                "{sentence;So much for that.}",
+                "{sentence;—The silent hours steal on,\n",
+                "And flaky darkness breaks within the east.}\n",
+                "}",
+            )
+        );
+        let options = ProjectOptions {
+            other_views: OtherViews::Milestones,
+        };
+        let sentences = doc.project_view_with("g", &options).unwrap();
+        assert_eq!(
+            sentences.encode_cptml(),
+            concat!(
+                "{poem;\n",
+                "<t/line|{sentence;I, by attorney, bless thee from thy mother,|t/line>\n",
+                "<t/line|Who prays continually for Richmond's good.}|t/line>\n",
+                "<t/line|{sentence;So much for that.}",
+                "{sentence;—The silent hours steal on,|t/>\n",
+                "<t/line|And flaky darkness breaks within the east.}|t/>\n",
+                "}",
+            )
+        );
+        assert_eq!(sentences.text(sentences.root()), doc.text(doc.root()));
+
+        let doc = dom("{p; <(t)a n=1|x}{q; y|(t)a>}");
+        let split = doc.project_view("t").unwrap();
+        assert_eq!(split.encode_cptml(), "{p; }{a n=1;{p;x}{q; y}}");
+        let doc = dom("{p !id=\"x\" n=1; <(t)a|x}{q; y|(t)a>}");
+        let split = doc.project_view("t").unwrap();
+        assert_eq!(
+            split.encode_cptml(),
+            "{p !id=\"x\" n=1; }{a;{p n=1;x}{q; y}}"
+        );
+        let (doc, _) = parse_document_recovering("<(t)a|", &ParseOptions::default());
+        assert!(Document::new(doc).project_view("g").is_err());
+    }
+}